@@ -4,7 +4,8 @@ printf 'methodname              %%        real[p,r]        user[p,r]        sys[
 
 for funcname in "abspath" "basename" "dirname" "isabs" "islink" \
                 "exists" "lexists" "split" "splitext" "relpath" \
-                "normpath" "realpath" "join" "expanduser" "expandvars"
+                "normpath" "realpath" "join" "expanduser" "expandvars" \
+                "realpath_chain" "realpath_cache"
 do
     python benchmarks.py -o result.txt $funcname >& /dev/null
     n=$(cat result.txt | jq -r '.Environment.parameters.loop')
@@ -1,5 +1,6 @@
 import sys
 import os
+import tempfile
 import fpath
 from benchmarker import Benchmarker
 
@@ -72,6 +73,68 @@ def bench_two_arg(arg):
                     func(B_DIR_PATH, B_FILE_PATH)
         print("=*=" * 40)
 
+def make_symlink_chains(root, length):
+    # link0 -> link1 -> ... -> target, and dir0/dir1/... where every
+    # level is reached through a symlink
+    os.mkdir(os.path.join(root, "target"))
+    for i in range(length):
+        dst = "link%d" % (i + 1) if i + 1 < length else "target"
+        os.symlink(dst, os.path.join(root, "link%d" % i))
+    parent = root
+    for i in range(length):
+        os.mkdir(os.path.join(parent, "real%d" % i))
+        os.symlink("real%d" % i, os.path.join(parent, "dir%d" % i))
+        parent = os.path.join(parent, "real%d" % i)
+    chain = os.path.join(root, "link0", "file")
+    deep = os.path.join(root, *["dir%d" % i for i in range(length)])
+    return chain, deep
+
+
+def bench_symlink_chain(arg):
+    for funcname in ("realpath_chain", ):
+        if arg is not None and arg != funcname:
+            continue
+        n = 1000
+        with tempfile.TemporaryDirectory() as tmp:
+            chain, deep = make_symlink_chains(tmp, 39)
+            with Benchmarker(n, width=30) as b:
+                @b("native.%s" % (funcname))
+                def _(bm):
+                    for i in bm:
+                        os.path.realpath(chain)
+                        os.path.realpath(deep)
+
+                @b("rust.%s" % (funcname))
+                def _(bm):
+                    for i in bm:
+                        fpath.realpath(chain)
+                        fpath.realpath(deep)
+        print("=*=" * 40)
+
+def bench_realpath_cache(arg):
+    for funcname in ("realpath_cache", ):
+        if arg is not None and arg != funcname:
+            continue
+        n = 1000
+        with tempfile.TemporaryDirectory() as tmp:
+            chain, deep = make_symlink_chains(tmp, 39)
+            cache = fpath.RealpathCache()
+            with Benchmarker(n, width=30) as b:
+                @b("uncached.%s" % (funcname))
+                def _(bm):
+                    for i in bm:
+                        fpath.realpath(chain)
+                        fpath.realpath(deep)
+
+                @b("cached.%s" % (funcname))
+                def _(bm):
+                    for i in bm:
+                        cache.realpath(chain)
+                        cache.realpath(deep)
+        print("=*=" * 40)
+
 arg = sys.argv[-1] if len(sys.argv) >= 2 else None
 bench_one_arg(arg)
 bench_two_arg(arg)
+bench_symlink_chain(arg)
+bench_realpath_cache(arg)
@@ -12,16 +12,50 @@ def abspath(path):
 
 basename = _fpath.basename
 realpath = _fpath.realpath
+resolve_trace = _fpath.resolve_trace
+resolve_virtual = _fpath.resolve_virtual
+RealpathCache = _fpath.RealpathCache
 dirname = _fpath.dirname
 isabs = _fpath.isabs
 normpath = _fpath.normpath
 split = _fpath.split
+splitall = _fpath.splitall
+joinall = _fpath.joinall
+match = _fpath.match
+full_match = _fpath.full_match
+walk = _fpath.walk
+find = _fpath.find
+FindFilter = _fpath.FindFilter
+listdir_typed = _fpath.listdir_typed
+scandir = _fpath.scandir
+disk_usage_tree = _fpath.disk_usage_tree
+which = _fpath.which
+which_all = _fpath.which_all
+which_cache_clear = _fpath.which_cache_clear
+glob = _fpath.glob
+iglob = _fpath.iglob
+GlobSet = _fpath.GlobSet
+IgnoreMatcher = _fpath.IgnoreMatcher
 splitext = _fpath.splitext
+splitext_all = _fpath.splitext_all
+splitext_known = _fpath.splitext_known
+COMPOUND_EXTENSIONS = _fpath.COMPOUND_EXTENSIONS
+components = _fpath.components
+ancestors = _fpath.ancestors
 expanduser = _fpath.expanduser
 expandvars = _fpath.expandvars
+relative_to = _fpath.relative_to
+is_relative_to = _fpath.is_relative_to
+secure_join = _fpath.secure_join
+is_within = _fpath.is_within
+symlink_target = _fpath.symlink_target
+if hasattr(_fpath, "resolve_beneath"):
+    resolve_beneath = _fpath.resolve_beneath
 
 
-def islink(path):
+def islink(path, *, dir_fd=None):
+    if dir_fd is not None:
+        return _fpath.islink(path, dir_fd=dir_fd)
     if isinstance(path, bytes):
         return ospath.islink(path)
     return _fpath.islink(path)
@@ -31,7 +65,9 @@ def join(path, *args):
     return _fpath.join(path, args)
 
 
-def exists(path):
+def exists(path, *, dir_fd=None, follow_symlinks=True):
+    if dir_fd is not None or not follow_symlinks:
+        return _fpath.exists(path, dir_fd=dir_fd, follow_symlinks=follow_symlinks)
     if type(path) == int:
         return ospath.exists(path)
     if (type(path) is bytes and b'\x00' in path) or (type(path) is str and '\x00' in path):
@@ -42,7 +78,25 @@ def exists(path):
         return ospath.exists(path)
 
 
-def relpath(path, start=None):
+def lexists(path, *, dir_fd=None):
+    if dir_fd is None:
+        return ospath.lexists(path)
+    return _fpath.lexists(path, dir_fd=dir_fd)
+
+
+def isfile(path, *, dir_fd=None, follow_symlinks=True):
+    if dir_fd is None and follow_symlinks:
+        return ospath.isfile(path)
+    return _fpath.isfile(path, dir_fd=dir_fd, follow_symlinks=follow_symlinks)
+
+
+def isdir(path, *, dir_fd=None, follow_symlinks=True):
+    if dir_fd is None and follow_symlinks:
+        return ospath.isdir(path)
+    return _fpath.isdir(path, dir_fd=dir_fd, follow_symlinks=follow_symlinks)
+
+
+def relpath(path, start=None, *, resolve=False):
     if not path:
         raise ValueError("no path specified")
     path_type = type(path)
@@ -56,7 +110,7 @@ def relpath(path, start=None):
     else:
         os.fspath(start)
     try:
-        ret = _fpath.relpath(path, start)
+        ret = _fpath.relpath(path, start, resolve=resolve)
     except TypeError:
         raise(TypeError('"%s" does not match "%s"' % (path_type, start_type)))
     return ret
@@ -78,9 +132,6 @@ getctime = ospath.getctime
 getmtime = ospath.getmtime
 getsize = ospath.getsize
 ismount = ospath.ismount
-isfile = ospath.isfile
-isdir = ospath.isdir
-lexists = ospath.lexists
 normcase = ospath.normcase
 samefile = ospath.samefile
 sameopenfile = ospath.sameopenfile
@@ -0,0 +1,9 @@
+"""Filename matching with shell patterns, compatible with the fnmatch module.
+
+Patterns are compiled once into a native matcher and kept in an LRU cache.
+"""
+from ._fpath import fnmatch, fnmatchcase
+from ._fpath import fnmatch_filter as filter
+from ._fpath import fnmatch_translate as translate
+
+__all__ = ["filter", "fnmatch", "fnmatchcase", "translate"]
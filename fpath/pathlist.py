@@ -0,0 +1,17 @@
+"""Helpers for search-path strings such as PATH, PYTHONPATH or MANPATH.
+
+Empty entries mean the current directory, as in POSIX. split and join keep
+them as "", so a path round-trips unchanged; prepend/append/remove/normalize
+pass every entry through normpath, which writes them as ".", and keep only
+the first occurrence of each.
+"""
+from ._fpath import pathlist_append as append
+from ._fpath import pathlist_expand as expand
+from ._fpath import pathlist_find as find
+from ._fpath import pathlist_join as join
+from ._fpath import pathlist_normalize as normalize
+from ._fpath import pathlist_prepend as prepend
+from ._fpath import pathlist_remove as remove
+from ._fpath import pathlist_split as split
+
+__all__ = ["append", "expand", "find", "join", "normalize", "prepend", "remove", "split"]
@@ -0,0 +1,243 @@
+//! Thin wrappers over the `*at()` syscalls, resolving relative paths against
+//! an optional directory descriptor instead of the process cwd.
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::RawFd;
+
+#[cfg(any(target_os = "linux", target_os = "emscripten"))]
+use libc::__errno_location as errno_location;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "dragonfly"))]
+use libc::__error as errno_location;
+#[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+use libc::__errno as errno_location;
+
+pub fn cstring(path: &OsStr) -> io::Result<CString> {
+    CString::new(path.as_bytes()).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+#[inline(always)]
+fn at_fd(dir_fd: Option<RawFd>) -> RawFd {
+    dir_fd.unwrap_or(libc::AT_FDCWD)
+}
+
+pub fn stat_at(dir_fd: Option<RawFd>, path: &OsStr, follow_symlinks: bool) -> io::Result<libc::stat> {
+    let c_path = cstring(path)?;
+    let flags = if follow_symlinks { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatat(at_fd(dir_fd), c_path.as_ptr(), &mut st, flags) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(st)
+}
+
+/// `lstat` then `readlink` relative to `dir_fd`: `None` when `path` isn't
+/// a symlink, otherwise the link text.
+pub fn readlink_at(dir_fd: Option<RawFd>, path: &OsStr) -> io::Result<Option<OsString>> {
+    let st = stat_at(dir_fd, path, false)?;
+    if st.st_mode & libc::S_IFMT != libc::S_IFLNK {
+        return Ok(None);
+    }
+    let c_path = cstring(path)?;
+    // `st_size` is only a hint: the link may be replaced in between
+    let mut buf = vec![0u8; (st.st_size.max(0) as usize).max(255) + 1];
+    loop {
+        let n = unsafe { libc::readlinkat(at_fd(dir_fd), c_path.as_ptr(), buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if (n as usize) < buf.len() {
+            buf.truncate(n as usize);
+            return Ok(Some(OsString::from_vec(buf)));
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+}
+
+#[inline(always)]
+pub fn is_dir_mode(mode: libc::mode_t) -> bool {
+    mode & libc::S_IFMT == libc::S_IFDIR
+}
+
+/// `S_IFMT` bits of `path`, or `None` when it can't be stat-ed.
+pub fn file_type_at(dir_fd: Option<RawFd>, path: &OsStr, follow_symlinks: bool) -> Option<libc::mode_t> {
+    stat_at(dir_fd, path, follow_symlinks).ok().map(|st| st.st_mode & libc::S_IFMT)
+}
+
+/// Path of the directory `fd` refers to, for resolving names relative to it.
+pub fn fd_dir_path(fd: RawFd) -> io::Result<OsString> {
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut st) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if !is_dir_mode(st.st_mode) {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+    }
+    fd_path(fd)
+}
+
+#[cfg(target_os = "linux")]
+pub fn fd_path(fd: RawFd) -> io::Result<OsString> {
+    Ok(std::fs::read_link(format!("/proc/self/fd/{}", fd))?.into_os_string())
+}
+
+#[cfg(target_os = "macos")]
+pub fn fd_path(fd: RawFd) -> io::Result<OsString> {
+    let mut buf = vec![0u8; libc::PATH_MAX as usize];
+    if unsafe { libc::fcntl(fd, libc::F_GETPATH, buf.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    buf.truncate(len);
+    Ok(OsString::from_vec(buf))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn fd_path(_fd: RawFd) -> io::Result<OsString> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+pub fn lexists_at(dir_fd: Option<RawFd>, path: &OsStr) -> bool {
+    stat_at(dir_fd, path, false).is_ok()
+}
+
+pub fn isdir_at(dir_fd: Option<RawFd>, path: &OsStr) -> bool {
+    stat_at(dir_fd, path, true).map(|st| is_dir_mode(st.st_mode)).unwrap_or(false)
+}
+
+/// Entry type as reported by `readdir`'s `d_type`, without extra syscalls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Dir,
+    Symlink,
+    Other,
+    Unknown,
+}
+
+impl EntryType {
+    fn from_d_type(d_type: u8) -> EntryType {
+        match d_type {
+            libc::DT_REG => EntryType::File,
+            libc::DT_DIR => EntryType::Dir,
+            libc::DT_LNK => EntryType::Symlink,
+            libc::DT_UNKNOWN => EntryType::Unknown,
+            _ => EntryType::Other,
+        }
+    }
+
+    fn from_mode(mode: libc::mode_t) -> EntryType {
+        match mode & libc::S_IFMT {
+            libc::S_IFREG => EntryType::File,
+            libc::S_IFDIR => EntryType::Dir,
+            libc::S_IFLNK => EntryType::Symlink,
+            _ => EntryType::Other,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryType::File => "file",
+            EntryType::Dir => "dir",
+            EntryType::Symlink => "symlink",
+            EntryType::Other => "other",
+            EntryType::Unknown => "unknown",
+        }
+    }
+}
+
+/// An open directory stream, read with `readdir`.
+pub struct Dir {
+    dirp: *mut libc::DIR,
+}
+
+unsafe impl Send for Dir {}
+
+impl Dir {
+    /// Open `path` (or "." when empty) relative to `dir_fd`.
+    pub fn open(dir_fd: Option<RawFd>, path: &OsStr) -> io::Result<Dir> {
+        let path = if path.is_empty() { OsStr::new(".") } else { path };
+        let c_path = cstring(path)?;
+        let fd = unsafe {
+            libc::openat(at_fd(dir_fd), c_path.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let dirp = unsafe { libc::fdopendir(fd) };
+        if dirp.is_null() {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+        Ok(Dir { dirp })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        unsafe { libc::dirfd(self.dirp) }
+    }
+
+    /// Next entry name and type, skipping "." and "..".
+    pub fn next_entry(&mut self) -> Option<io::Result<(OsString, EntryType)>> {
+        self.next_entry_ino().map(|r| r.map(|(name, entry_type, _)| (name, entry_type)))
+    }
+
+    /// Like `next_entry`, with the inode number `readdir` reported.
+    pub fn next_entry_ino(&mut self) -> Option<io::Result<TypedEntry>> {
+        loop {
+            // `readdir` only sets errno on failure
+            unsafe { *errno_location() = 0 };
+            let ent = unsafe { libc::readdir(self.dirp) };
+            if ent.is_null() {
+                let e = io::Error::last_os_error();
+                return match e.raw_os_error() {
+                    Some(0) | None => None,
+                    Some(_) => Some(Err(e)),
+                };
+            }
+            let name = unsafe { CStr::from_ptr((*ent).d_name.as_ptr()) }.to_bytes();
+            if name == b"." || name == b".." {
+                continue;
+            }
+            let (d_type, d_ino) = unsafe { ((*ent).d_type, (*ent).d_ino) };
+            return Some(Ok((OsString::from_vec(name.to_vec()), EntryType::from_d_type(d_type), d_ino)));
+        }
+    }
+
+    /// True when the entry `name` is a directory, following symlinks and
+    /// only stat-ing when `d_type` can't tell.
+    pub fn entry_is_dir(&self, name: &OsStr, entry_type: EntryType) -> bool {
+        match entry_type {
+            EntryType::Dir => true,
+            EntryType::Symlink | EntryType::Unknown => isdir_at(Some(self.fd()), name),
+            _ => false,
+        }
+    }
+}
+
+/// A directory entry's name, type and inode number.
+pub type TypedEntry = (OsString, EntryType, u64);
+
+/// Every entry of `path` with its type and inode. Types come from `d_type`
+/// and only filesystems that leave it unset cost an `lstat` per entry.
+pub fn read_dir_typed(path: &OsStr) -> io::Result<Vec<TypedEntry>> {
+    let mut dir = Dir::open(None, path)?;
+    let mut entries = vec![];
+    while let Some(entry) = dir.next_entry_ino() {
+        let (name, mut entry_type, ino) = entry?;
+        if entry_type == EntryType::Unknown {
+            entry_type = match stat_at(Some(dir.fd()), &name, false) {
+                Ok(st) => EntryType::from_mode(st.st_mode),
+                Err(_) => EntryType::Unknown,
+            };
+        }
+        entries.push((name, entry_type, ino));
+    }
+    Ok(entries)
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        unsafe { libc::closedir(self.dirp) };
+    }
+}
@@ -0,0 +1,215 @@
+//! Resolution of a path beneath a directory through file descriptors, so a
+//! symlink swapped between two steps can't redirect it outside. Uses
+//! `openat2(RESOLVE_BENEATH)` when the kernel has it and otherwise walks
+//! one `O_PATH` descriptor per component.
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::RawFd;
+
+use crate::at::{cstring, fd_path};
+use crate::walkdir::join_name;
+
+/// `MAXSYMLINKS` on Linux.
+const MAX_HOPS: usize = 40;
+
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// An owned descriptor, closed on drop unless handed out with `into_raw`.
+pub struct Fd(RawFd);
+
+impl Fd {
+    pub fn raw(&self) -> RawFd {
+        self.0
+    }
+
+    pub fn into_raw(self) -> RawFd {
+        let fd = self.0;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn errno(n: i32) -> io::Error {
+    io::Error::from_raw_os_error(n)
+}
+
+fn open_at(dir_fd: RawFd, path: &OsStr, flags: i32) -> io::Result<Fd> {
+    let c_path = cstring(path)?;
+    let fd = unsafe { libc::openat(dir_fd, c_path.as_ptr(), flags | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Fd(fd))
+}
+
+fn fstat(fd: RawFd) -> io::Result<libc::stat> {
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut st) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(st)
+}
+
+/// Link text of the symlink `fd` was opened on with `O_PATH|O_NOFOLLOW`.
+fn read_link_fd(fd: RawFd) -> io::Result<OsString> {
+    let mut buf = vec![0u8; libc::PATH_MAX as usize];
+    let n = unsafe { libc::readlinkat(fd, c"".as_ptr(), buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    buf.truncate(n as usize);
+    Ok(OsString::from_vec(buf))
+}
+
+fn openat2(root: RawFd, path: &OsStr, flags: i32) -> io::Result<Fd> {
+    let c_path = cstring(if path.is_empty() { OsStr::new(".") } else { path })?;
+    let how = OpenHow {
+        flags: (flags | libc::O_CLOEXEC) as u64,
+        mode: 0,
+        resolve: libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS,
+    };
+    loop {
+        let fd = unsafe {
+            libc::syscall(libc::SYS_openat2, root, c_path.as_ptr(), &how as *const OpenHow, std::mem::size_of::<OpenHow>())
+        };
+        if fd >= 0 {
+            return Ok(Fd(fd as RawFd));
+        }
+        let e = io::Error::last_os_error();
+        // the kernel asks for a retry when a concurrent rename could have
+        // made `..` unsafe to check
+        if e.raw_os_error() != Some(libc::EAGAIN) {
+            return Err(e);
+        }
+    }
+}
+
+/// Components of `path` in reverse, so the next one is popped off the end.
+fn components(path: &OsStr) -> Vec<OsString> {
+    path.as_bytes().split(|&c| c == b'/').rev().map(|x| OsString::from_vec(x.to_vec())).collect()
+}
+
+/// `openat2` done by hand: every component is opened `O_NOFOLLOW` from its
+/// parent's descriptor and symlinks are expanded in place, so nothing is
+/// looked up by a path that could change underneath. Returns the opened
+/// descriptor and the names leading to it from `root`.
+fn walk(root: RawFd, path: &OsStr, flags: i32) -> io::Result<(Fd, Vec<OsString>)> {
+    let path_flags = libc::O_PATH | libc::O_NOFOLLOW;
+    let mut stack: Vec<(Fd, OsString)> = vec![];
+    if path.as_bytes().starts_with(b"/") {
+        return Err(errno(libc::EXDEV));
+    }
+    let mut pending = components(path);
+    let mut hops = 0;
+    while let Some(name) = pending.pop() {
+        if name.is_empty() || name == "." {
+            continue;
+        }
+        if name == ".." {
+            if stack.pop().is_none() {
+                return Err(errno(libc::EXDEV));
+            }
+            continue;
+        }
+        let cur = stack.last().map(|x| x.0.raw()).unwrap_or(root);
+        let fd = open_at(cur, &name, path_flags)?;
+        let st = fstat(fd.raw())?;
+        let mode = st.st_mode & libc::S_IFMT;
+        if mode == libc::S_IFLNK && !(pending.is_empty() && flags & libc::O_NOFOLLOW != 0) {
+            hops += 1;
+            if hops > MAX_HOPS {
+                return Err(errno(libc::ELOOP));
+            }
+            let target = read_link_fd(fd.raw())?;
+            if target.as_bytes().starts_with(b"/") {
+                return Err(errno(libc::EXDEV));
+            }
+            pending.extend(components(&target));
+            continue;
+        }
+        if !pending.is_empty() && mode != libc::S_IFDIR {
+            return Err(errno(libc::ENOTDIR));
+        }
+        stack.push((fd, name));
+    }
+    let (parent, last) = match stack.pop() {
+        Some((fd, name)) if flags & libc::O_PATH != 0 => {
+            let mut names: Vec<OsString> = stack.into_iter().map(|x| x.1).collect();
+            names.push(name);
+            return Ok((fd, names));
+        }
+        Some((fd, name)) => (stack.last().map(|x| x.0.raw()).unwrap_or(root), Some((fd, name))),
+        None => (root, None),
+    };
+    // reopen with the requested flags and make sure it's still the file
+    // the walk ended on
+    let (fd, name) = match last {
+        Some((checked, name)) => {
+            let fd = open_at(parent, &name, flags | libc::O_NOFOLLOW)?;
+            let (a, b) = (fstat(fd.raw())?, fstat(checked.raw())?);
+            if (a.st_dev, a.st_ino) != (b.st_dev, b.st_ino) {
+                return Err(errno(libc::EAGAIN));
+            }
+            (fd, Some(name))
+        }
+        None => (open_at(root, OsStr::new("."), flags)?, None),
+    };
+    let mut names: Vec<OsString> = stack.into_iter().map(|x| x.1).collect();
+    names.extend(name);
+    Ok((fd, names))
+}
+
+/// Open `path` beneath `root`, refusing absolute symlinks, `..` past
+/// `root` and magic links with `EXDEV`. Returns the canonical path of what
+/// was opened together with the descriptor, opened with `flags`.
+pub fn resolve_beneath(root: &OsStr, path: &OsStr, flags: i32, use_openat2: bool) -> io::Result<(OsString, Fd)> {
+    let root_fd = open_at(libc::AT_FDCWD, root, libc::O_PATH | libc::O_DIRECTORY)?;
+    if use_openat2 {
+        match openat2(root_fd.raw(), path, flags) {
+            Ok(fd) => {
+                if let Ok(p) = fd_path(fd.raw()) {
+                    return Ok((p, fd));
+                }
+            }
+            Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let (fd, names) = walk(root_fd.raw(), path, flags)?;
+    let mut ret = match fd_path(root_fd.raw()) {
+        Ok(p) => p,
+        Err(_) => std::fs::canonicalize(root)?.into_os_string(),
+    };
+    for name in names {
+        ret = join_name(&ret, &name);
+    }
+    Ok((ret, fd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve_beneath;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn beneath_root() {
+        for use_openat2 in [true, false] {
+            let (path, _) = resolve_beneath(OsStr::new("/"), OsStr::new("usr/./bin/.."), libc::O_PATH, use_openat2).unwrap();
+            assert_eq!(path, std::fs::canonicalize("/usr").unwrap().into_os_string());
+            let e = resolve_beneath(OsStr::new("/usr"), OsStr::new("../etc"), libc::O_PATH, use_openat2).err().unwrap();
+            assert_eq!(e.raw_os_error(), Some(libc::EXDEV));
+        }
+    }
+}
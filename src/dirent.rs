@@ -0,0 +1,106 @@
+use std::ffi::OsString;
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::at::EntryType;
+use crate::walkdir::os2py;
+
+/// A directory entry like `os.DirEntry`: the type comes from the listing and
+/// `stat()` results are cached on first use.
+#[pyclass(module = "fpath._fpath")]
+pub struct DirEntry {
+    name: OsString,
+    path: OsString,
+    entry_type: EntryType,
+    ino: u64,
+    is_bytes: bool,
+    stat: Option<PyObject>,
+    lstat: Option<PyObject>,
+}
+
+impl DirEntry {
+    pub fn new(name: OsString, path: OsString, entry_type: EntryType, ino: u64, is_bytes: bool) -> Self {
+        DirEntry { name, path, entry_type, ino, is_bytes, stat: None, lstat: None }
+    }
+
+    fn fetch_stat(&mut self, py: Python, follow_symlinks: bool) -> PyResult<PyObject> {
+        let follow = follow_symlinks && self.entry_type == EntryType::Symlink;
+        let cached = if follow { &mut self.stat } else { &mut self.lstat };
+        if let Some(ref st) = cached {
+            return Ok(st.clone_ref(py));
+        }
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("follow_symlinks", follow)?;
+        let path = os2py(py, &self.path, self.is_bytes);
+        let st: PyObject = py.import("os")?.getattr("stat")?.call((path,), Some(kwargs))?.into();
+        *cached = Some(st.clone_ref(py));
+        Ok(st)
+    }
+
+    /// `S_IFMT` bits of the entry, following symlinks when asked. Entries
+    /// that vanished or whose link is broken have none, like `os.DirEntry`.
+    fn file_type(&mut self, py: Python, follow_symlinks: bool) -> PyResult<Option<libc::mode_t>> {
+        let mode = match self.entry_type {
+            EntryType::Dir => return Ok(Some(libc::S_IFDIR)),
+            EntryType::File => return Ok(Some(libc::S_IFREG)),
+            EntryType::Symlink if !follow_symlinks => return Ok(Some(libc::S_IFLNK)),
+            _ => match self.fetch_stat(py, follow_symlinks) {
+                Ok(st) => st.getattr(py, "st_mode")?.extract::<u32>(py)? as libc::mode_t,
+                Err(e) if e.is_instance_of::<pyo3::exceptions::PyFileNotFoundError>(py) => return Ok(None),
+                Err(e) => return Err(e),
+            },
+        };
+        Ok(Some(mode & libc::S_IFMT))
+    }
+}
+
+#[pymethods]
+impl DirEntry {
+    #[getter]
+    fn name(&self, py: Python) -> PyObject {
+        os2py(py, &self.name, self.is_bytes)
+    }
+
+    #[getter]
+    fn path(&self, py: Python) -> PyObject {
+        os2py(py, &self.path, self.is_bytes)
+    }
+
+    /// `file`, `dir`, `symlink` or `other`, as reported by `readdir`.
+    #[getter]
+    fn d_type(&self) -> &'static str {
+        self.entry_type.as_str()
+    }
+
+    fn inode(&self) -> u64 {
+        self.ino
+    }
+
+    #[pyo3(signature = (*, follow_symlinks=true))]
+    fn is_dir(&mut self, py: Python, follow_symlinks: bool) -> PyResult<bool> {
+        Ok(self.file_type(py, follow_symlinks)? == Some(libc::S_IFDIR))
+    }
+
+    #[pyo3(signature = (*, follow_symlinks=true))]
+    fn is_file(&mut self, py: Python, follow_symlinks: bool) -> PyResult<bool> {
+        Ok(self.file_type(py, follow_symlinks)? == Some(libc::S_IFREG))
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.entry_type == EntryType::Symlink
+    }
+
+    #[pyo3(signature = (*, follow_symlinks=true))]
+    fn stat(&mut self, py: Python, follow_symlinks: bool) -> PyResult<PyObject> {
+        self.fetch_stat(py, follow_symlinks)
+    }
+
+    fn __fspath__(&self, py: Python) -> PyObject {
+        os2py(py, &self.path, self.is_bytes)
+    }
+
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        Ok(format!("<DirEntry {}>", self.name(py).as_ref(py).repr()?))
+    }
+}
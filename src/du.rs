@@ -0,0 +1,187 @@
+//! `du`-style disk usage: directories are read by a pool of threads and the
+//! sizes are summed up the tree once everything has been listed.
+use std::collections::{HashSet, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::fs::Metadata;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use crate::walkdir::{join_name, MAX_THREADS};
+
+pub struct DuOptions {
+    pub apparent: bool,
+    pub follow_symlinks: bool,
+    pub xdev: bool,
+}
+
+struct Node {
+    path: OsString,
+    parent: Option<usize>,
+    depth: usize,
+    size: u64,
+}
+
+struct State {
+    nodes: Vec<Node>,
+    queue: VecDeque<usize>,
+    active: usize,
+}
+
+struct Du<'a> {
+    opts: &'a DuOptions,
+    root_dev: u64,
+    state: Mutex<State>,
+    cv: Condvar,
+    // `(dev, ino)` of hardlinked files, and of directories when following
+    // symlinks, so nothing is counted twice
+    seen: Mutex<HashSet<(u64, u64)>>,
+    errors: Mutex<Vec<(io::Error, OsString)>>,
+}
+
+impl Du<'_> {
+    fn stat(&self, path: &OsStr) -> io::Result<Metadata> {
+        let meta = std::fs::symlink_metadata(path)?;
+        if self.opts.follow_symlinks && meta.file_type().is_symlink() {
+            return Ok(std::fs::metadata(path).unwrap_or(meta));
+        }
+        Ok(meta)
+    }
+
+    fn size_of(&self, meta: &Metadata) -> u64 {
+        if self.opts.apparent {
+            meta.size()
+        } else {
+            meta.blocks() * 512
+        }
+    }
+
+    /// True the first time `meta` is seen, or when it can't be seen twice.
+    fn first_visit(&self, meta: &Metadata) -> bool {
+        let shared = if meta.is_dir() { self.opts.follow_symlinks } else { meta.nlink() > 1 };
+        !shared || self.seen.lock().unwrap_or_else(|e| e.into_inner()).insert((meta.dev(), meta.ino()))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Size the entries of directory `idx`: files are added to it, child
+    /// directories become new nodes. What can't be read is left out and
+    /// recorded in `errors`.
+    fn scan(&self, idx: usize, path: &OsStr, depth: usize) {
+        let mut size = 0;
+        let mut dirs = vec![];
+        let mut errors = vec![];
+        let entries = std::fs::read_dir(path).map_err(|e| errors.push((e, path.to_os_string())));
+        for entry in entries.into_iter().flatten() {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    errors.push((e, path.to_os_string()));
+                    break;
+                }
+            };
+            let child = join_name(path, &entry.file_name());
+            let meta = match self.stat(&child) {
+                Ok(m) => m,
+                Err(e) => {
+                    errors.push((e, child));
+                    continue;
+                }
+            };
+            if self.opts.xdev && meta.dev() != self.root_dev {
+                continue;
+            }
+            if !self.first_visit(&meta) {
+                continue;
+            }
+            if meta.is_dir() {
+                dirs.push(Node { path: child, parent: Some(idx), depth: depth + 1, size: self.size_of(&meta) });
+            } else {
+                size += self.size_of(&meta);
+            }
+        }
+        if !errors.is_empty() {
+            self.errors.lock().unwrap_or_else(|e| e.into_inner()).extend(errors);
+        }
+        let mut state = self.lock();
+        state.nodes[idx].size += size;
+        for node in dirs {
+            let i = state.nodes.len();
+            state.nodes.push(node);
+            state.queue.push_back(i);
+        }
+        state.active -= 1;
+        self.cv.notify_all();
+    }
+
+    fn work(&self) {
+        loop {
+            let (idx, path, depth) = {
+                let mut state = self.lock();
+                loop {
+                    if let Some(i) = state.queue.pop_front() {
+                        state.active += 1;
+                        let node = &state.nodes[i];
+                        break (i, node.path.clone(), node.depth);
+                    }
+                    if state.active == 0 {
+                        return;
+                    }
+                    state = self.cv.wait(state).unwrap_or_else(|e| e.into_inner());
+                }
+            };
+            self.scan(idx, &path, depth);
+        }
+    }
+}
+
+/// Sizes of a `disk_usage_tree` and what couldn't be read for it.
+pub type Usage = (Vec<(OsString, u64)>, Vec<(io::Error, OsString)>);
+
+/// Total size of every directory below `root` (inclusive), reported down
+/// to `max_depth` levels; deeper directories still count toward their
+/// ancestors. Directories and entries that can't be read are left out of
+/// the totals and returned with the path that failed.
+pub fn disk_usage_tree(root: &OsStr, opts: &DuOptions, max_depth: Option<usize>) -> io::Result<Usage> {
+    let meta = if opts.follow_symlinks { std::fs::metadata(root)? } else { std::fs::symlink_metadata(root)? };
+    let du = Du {
+        opts,
+        root_dev: meta.dev(),
+        state: Mutex::new(State { nodes: vec![], queue: VecDeque::new(), active: 0 }),
+        cv: Condvar::new(),
+        seen: Mutex::new(HashSet::new()),
+        errors: Mutex::new(vec![]),
+    };
+    du.first_visit(&meta);
+    let root_node = Node { path: root.to_os_string(), parent: None, depth: 0, size: du.size_of(&meta) };
+    {
+        let mut state = du.lock();
+        state.nodes.push(root_node);
+        if meta.is_dir() {
+            state.queue.push_back(0);
+        }
+    }
+    let nthreads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(MAX_THREADS);
+    thread::scope(|s| {
+        for _ in 0..nthreads {
+            s.spawn(|| du.work());
+        }
+    });
+    let errors = du.errors.into_inner().unwrap_or_else(|e| e.into_inner());
+    let mut nodes = du.state.into_inner().unwrap_or_else(|e| e.into_inner()).nodes;
+    // children always come after their parent, so one backward pass sums up
+    for i in (1..nodes.len()).rev() {
+        if let Some(p) = nodes[i].parent {
+            nodes[p].size += nodes[i].size;
+        }
+    }
+    let sizes = nodes
+        .into_iter()
+        .filter(|x| max_depth.map_or(true, |m| x.depth <= m))
+        .map(|x| (x.path, x.size))
+        .collect();
+    Ok((sizes, errors))
+}
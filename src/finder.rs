@@ -0,0 +1,529 @@
+//! `find(1)` style predicates, checked in Rust with the GIL released so
+//! only matching paths are handed to Python. Used by `find` and by the
+//! `filter=` argument of `walk` and `scandir`.
+use std::ffi::{OsStr, OsString};
+use std::fs::Metadata;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use uzers::{get_group_by_name, get_user_by_name};
+
+use crate::at::TypedEntry;
+use crate::pattern::Segment;
+use crate::utils::pyobj2str;
+use crate::walkdir::{join_name, oserror, os2py, Ancestor, Pool, Scan};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cmp {
+    Lt,
+    Eq,
+    Gt,
+}
+
+/// Split a leading comparison off `s`: `>`/`+` for more than, `<`/`-` for
+/// less than, and `=` or nothing for an exact match.
+fn parse_cmp(s: &str) -> (Cmp, &str) {
+    let s = s.trim();
+    if let Some(rest) = s.strip_prefix('>').or_else(|| s.strip_prefix('+')) {
+        (Cmp::Gt, rest)
+    } else if let Some(rest) = s.strip_prefix('<').or_else(|| s.strip_prefix('-')) {
+        (Cmp::Lt, rest)
+    } else {
+        (Cmp::Eq, s.strip_prefix('=').unwrap_or(s))
+    }
+}
+
+/// Split a trailing unit off `s` and return the number and the unit's
+/// multiplier. A missing unit uses `default`.
+fn parse_unit(s: &str, units: &[(char, f64)], default: f64) -> Result<(f64, f64), String> {
+    let (num, mult) = match units.iter().find(|(u, _)| s.ends_with(*u)) {
+        Some((u, mult)) => (&s[..s.len() - u.len_utf8()], *mult),
+        None => (s, default),
+    };
+    match num.trim().parse::<f64>() {
+        Ok(n) if n >= 0.0 => Ok((n, mult)),
+        _ => Err(format!("invalid number: {:?}", s)),
+    }
+}
+
+const SIZE_UNITS: &[(char, u64)] = &[
+    ('b', 512),
+    ('c', 1),
+    ('w', 2),
+    ('k', 1024),
+    ('K', 1024),
+    ('M', 1048576),
+    ('G', 1073741824),
+    ('T', 1099511627776),
+];
+const TIME_UNITS: &[(char, f64)] = &[('s', 1.0), ('m', 60.0), ('h', 3600.0), ('d', 86400.0), ('w', 604800.0)];
+
+/// `size`: 512-byte blocks by default like `find -size`, or with a
+/// `b`/`c`/`w`/`k`/`M`/`G`/`T` suffix. Returns the count and the unit.
+pub fn parse_size(s: &str) -> Result<(Cmp, u64, u64), String> {
+    let (cmp, rest) = parse_cmp(s);
+    let (num, unit) = match SIZE_UNITS.iter().find(|(u, _)| rest.ends_with(*u)) {
+        Some((u, unit)) => (&rest[..rest.len() - u.len_utf8()], *unit),
+        None => (rest, 512),
+    };
+    match num.trim().parse::<u64>() {
+        Ok(n) => Ok((cmp, n, unit)),
+        Err(_) => Err(format!("invalid size: {:?}", s)),
+    }
+}
+
+/// `mtime`: age in days by default, or with a `s`/`m`/`h`/`d`/`w` suffix.
+/// An exact age matches the whole unit like `find -mtime n` does.
+pub fn parse_age(s: &str) -> Result<(Cmp, f64, f64), String> {
+    let (cmp, rest) = parse_cmp(s);
+    let (n, unit) = parse_unit(rest, TIME_UNITS, 86400.0)?;
+    Ok((cmp, n, unit))
+}
+
+/// `type`: any of `f`, `d`, `l`, `p`, `s`, `c` and `b`, optionally comma
+/// separated.
+pub fn parse_types(s: &str) -> Result<Vec<char>, String> {
+    let types: Vec<char> = s.chars().filter(|c| *c != ',').collect();
+    match types.iter().find(|c| !"fdlpscb".contains(**c)) {
+        Some(c) => Err(format!("unknown type: {:?}", c)),
+        None if types.is_empty() => Err("empty type".to_string()),
+        None => Ok(types),
+    }
+}
+
+fn type_char(meta: &Metadata) -> char {
+    let ft = meta.file_type();
+    if ft.is_symlink() {
+        'l'
+    } else if ft.is_dir() {
+        'd'
+    } else if ft.is_file() {
+        'f'
+    } else if ft.is_fifo() {
+        'p'
+    } else if ft.is_socket() {
+        's'
+    } else if ft.is_char_device() {
+        'c'
+    } else {
+        'b'
+    }
+}
+
+fn timestamp(meta: &Metadata) -> f64 {
+    meta.mtime() as f64 + meta.mtime_nsec() as f64 / 1e9
+}
+
+pub fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+/// `stat` when following links, falling back to `lstat` for broken ones
+/// the way `find -L` does, otherwise `lstat`.
+fn entry_meta(path: &OsStr, followlinks: bool) -> io::Result<Metadata> {
+    if followlinks {
+        if let Ok(m) = std::fs::metadata(path) {
+            return Ok(m);
+        }
+    }
+    std::fs::symlink_metadata(path)
+}
+
+#[derive(Default)]
+pub struct Filter {
+    pub types: Option<Vec<char>>,
+    pub name: Option<Segment>,
+    pub size: Option<(Cmp, u64, u64)>,
+    pub newer: Option<f64>,
+    pub mtime: Option<(Cmp, f64, f64)>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub mindepth: usize,
+    pub maxdepth: Option<usize>,
+    pub xdev: bool,
+}
+
+impl Filter {
+    fn needs_meta(&self) -> bool {
+        self.types.is_some()
+            || self.size.is_some()
+            || self.newer.is_some()
+            || self.mtime.is_some()
+            || self.uid.is_some()
+            || self.gid.is_some()
+            || self.xdev
+    }
+
+    fn is_match(&self, name: &OsStr, meta: Option<&Metadata>, now: f64) -> bool {
+        if let Some(ref seg) = self.name {
+            if !seg.is_match(&name.to_string_lossy()) {
+                return false;
+            }
+        }
+        let meta = match meta {
+            Some(m) => m,
+            None => return true,
+        };
+        if let Some(ref types) = self.types {
+            if !types.contains(&type_char(meta)) {
+                return false;
+            }
+        }
+        if let Some((cmp, n, unit)) = self.size {
+            // like find, the size is rounded up to whole units first
+            let units = meta.size().div_ceil(unit);
+            let ok = match cmp {
+                Cmp::Lt => units < n,
+                Cmp::Eq => units == n,
+                Cmp::Gt => units > n,
+            };
+            if !ok {
+                return false;
+            }
+        }
+        if let Some(t) = self.newer {
+            if timestamp(meta) <= t {
+                return false;
+            }
+        }
+        if let Some((cmp, n, unit)) = self.mtime {
+            // like find, the age is rounded down to whole units first
+            let age = ((now - timestamp(meta)) / unit).floor();
+            let ok = match cmp {
+                Cmp::Lt => age < n,
+                Cmp::Eq => age == n,
+                Cmp::Gt => age > n,
+            };
+            if !ok {
+                return false;
+            }
+        }
+        self.uid.map_or(true, |uid| meta.uid() == uid) && self.gid.map_or(true, |gid| meta.gid() == gid)
+    }
+
+    /// Whether entries at `depth` are reported at all.
+    fn lists(&self, depth: usize) -> bool {
+        depth >= self.mindepth && self.maxdepth.map_or(true, |m| depth <= m)
+    }
+
+    /// Whether a directory at `depth` is walked into.
+    fn descends(&self, depth: usize, meta: Option<&Metadata>, top_dev: Option<u64>) -> bool {
+        self.maxdepth.map_or(true, |m| depth < m) && (!self.xdev || meta.map(|m| m.dev()) == top_dev)
+    }
+
+    /// Filter one listing of a walk at `depth` (that of its entries). The
+    /// subdirectories to walk into are returned apart from the matching
+    /// ones, since a directory that doesn't match may still hold entries
+    /// that do. Entries that can't be stat'ed are returned as errors.
+    pub fn select(&self, dirpath: &OsStr, scan: Scan, depth: usize, followlinks: bool, top_dev: Option<u64>, now: f64) -> Selected {
+        let mut ret = Selected { dirs: vec![], files: vec![], descend: vec![], errors: vec![] };
+        let needs_meta = self.needs_meta();
+        let lists = self.lists(depth);
+        let meta = |name: &OsStr, errors: &mut Vec<(io::Error, OsString)>| -> Result<Option<Metadata>, ()> {
+            if !needs_meta {
+                return Ok(None);
+            }
+            let path = join_name(dirpath, name);
+            entry_meta(&path, followlinks).map(Some).map_err(|e| errors.push((e, path)))
+        };
+        for (name, is_symlink) in scan.dirs {
+            let meta = match meta(&name, &mut ret.errors) {
+                Ok(m) => m,
+                Err(()) => continue,
+            };
+            if (followlinks || !is_symlink) && self.descends(depth, meta.as_ref(), top_dev) {
+                ret.descend.push(name.clone());
+            }
+            if lists && self.is_match(&name, meta.as_ref(), now) {
+                ret.dirs.push((name, is_symlink));
+            }
+        }
+        if lists {
+            for name in scan.files {
+                let meta = match meta(&name, &mut ret.errors) {
+                    Ok(m) => m,
+                    Err(()) => continue,
+                };
+                if self.is_match(&name, meta.as_ref(), now) {
+                    ret.files.push(name);
+                }
+            }
+        }
+        ret
+    }
+
+    /// The entries of a `scandir` of `dirpath` that match, taking them to
+    /// be at depth 1. Entries gone before they could be stat'ed are left
+    /// out.
+    pub fn select_entries(&self, dirpath: &OsStr, entries: Vec<TypedEntry>) -> io::Result<Vec<TypedEntry>> {
+        if !self.lists(1) {
+            return Ok(vec![]);
+        }
+        let now = now();
+        let top_dev = if self.xdev { Some(std::fs::metadata(dirpath)?.dev()) } else { None };
+        let mut ret = vec![];
+        for entry in entries {
+            let meta = if self.needs_meta() {
+                match std::fs::symlink_metadata(join_name(dirpath, &entry.0)) {
+                    Ok(m) => Some(m),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                }
+            } else {
+                None
+            };
+            if top_dev.is_some() && meta.as_ref().map(|m| m.dev()) != top_dev {
+                continue;
+            }
+            if self.is_match(&entry.0, meta.as_ref(), now) {
+                ret.push(entry);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// One directory listing of a filtered walk.
+pub struct Selected {
+    /// Matching subdirectories, with their symlink flag.
+    pub dirs: Vec<(OsString, bool)>,
+    pub files: Vec<OsString>,
+    /// Subdirectories to walk into, matching or not.
+    pub descend: Vec<OsString>,
+    pub errors: Vec<(io::Error, OsString)>,
+}
+
+impl Selected {
+    /// A listing with no filter: everything matches.
+    pub fn all(scan: Scan, followlinks: bool) -> Selected {
+        let descend = scan.dirs.iter().filter(|(_, is_symlink)| followlinks || !is_symlink).map(|(name, _)| name.clone()).collect();
+        Selected { dirs: scan.dirs, files: scan.files, descend, errors: vec![] }
+    }
+}
+
+/// A uid or gid given either as a number or as a name.
+fn owner_id(obj: &PyAny, lookup: fn(&str) -> Option<u32>, what: &str) -> PyResult<u32> {
+    if let Ok(id) = obj.extract::<u32>() {
+        return Ok(id);
+    }
+    let name: &str = obj.extract()?;
+    lookup(name).ok_or_else(|| exceptions::PyValueError::new_err(format!("unknown {}: {:?}", what, name)))
+}
+
+/// A declarative `find(1)` filter, given as `filter=` to `walk` and
+/// `scandir`. `user`/`group` are resolved and `newer` is stat'ed once,
+/// when the filter is created.
+#[pyclass(module = "fpath._fpath")]
+pub struct FindFilter {
+    pub filter: Arc<Filter>,
+}
+
+#[pymethods]
+impl FindFilter {
+    #[new]
+    #[pyo3(signature = (*, r#type=None, name=None, size=None, newer=None, mtime=None, user=None, group=None, mindepth=0, maxdepth=None, xdev=false))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        py: Python,
+        r#type: Option<&str>,
+        name: Option<&str>,
+        size: Option<&PyAny>,
+        newer: Option<&PyAny>,
+        mtime: Option<&PyAny>,
+        user: Option<&PyAny>,
+        group: Option<&PyAny>,
+        mindepth: usize,
+        maxdepth: Option<usize>,
+        xdev: bool,
+    ) -> PyResult<Self> {
+        let mut filter = Filter { mindepth, maxdepth, xdev, ..Default::default() };
+        if let Some(t) = r#type {
+            filter.types = Some(parse_types(t).map_err(exceptions::PyValueError::new_err)?);
+        }
+        filter.name = name.map(|x| Segment::compile(x, true));
+        if let Some(x) = size {
+            let s = x.str()?.to_string();
+            filter.size = Some(parse_size(&s).map_err(exceptions::PyValueError::new_err)?);
+        }
+        if let Some(x) = mtime {
+            let s = x.str()?.to_string();
+            filter.mtime = Some(parse_age(&s).map_err(exceptions::PyValueError::new_err)?);
+        }
+        if let Some(x) = newer {
+            let (newer_str, _) = pyobj2str(&py, x).map_err(exceptions::PyTypeError::new_err)?;
+            let meta = std::fs::metadata(&newer_str).map_err(|e| oserror(&e, newer_str.as_ref()))?;
+            filter.newer = Some(timestamp(&meta));
+        }
+        if let Some(x) = user {
+            filter.uid = Some(owner_id(x, |n| get_user_by_name(n).map(|u| u.uid()), "user")?);
+        }
+        if let Some(x) = group {
+            filter.gid = Some(owner_id(x, |n| get_group_by_name(n).map(|g| g.gid()), "group")?);
+        }
+        Ok(FindFilter { filter: Arc::new(filter) })
+    }
+}
+
+struct Visit {
+    path: OsString,
+    depth: usize,
+    // `(is_dir, is_symlink)` from the parent's listing, `None` for the top
+    kind: Option<(bool, bool)>,
+    ancestors: Option<Arc<Ancestor>>,
+}
+
+type FindError = (io::Error, OsString);
+
+#[pyclass(module = "fpath._fpath")]
+pub struct Find {
+    pool: Arc<Pool>,
+    filter: Arc<Filter>,
+    stack: Vec<Visit>,
+    top_dev: Option<u64>,
+    now: f64,
+    onerror: Option<PyObject>,
+    pending_error: Option<FindError>,
+    is_bytes: bool,
+}
+
+impl Find {
+    pub fn new(top: OsString, filter: Arc<Filter>, followlinks: bool, onerror: Option<PyObject>, is_bytes: bool) -> Self {
+        Find {
+            pool: Pool::new(followlinks, false, followlinks, None),
+            filter,
+            stack: vec![Visit { path: top, depth: 0, kind: None, ancestors: None }],
+            top_dev: None,
+            now: now(),
+            onerror,
+            pending_error: None,
+            is_bytes,
+        }
+    }
+
+    fn next_path(&mut self) -> Option<Result<OsString, FindError>> {
+        if let Some(e) = self.pending_error.take() {
+            return Some(Err(e));
+        }
+        let followlinks = self.pool.followlinks();
+        while let Some(visit) = self.stack.pop() {
+            let name = match memchr::memrchr(b'/', visit.path.as_bytes()) {
+                Some(i) if i + 1 < visit.path.len() => OsStr::from_bytes(&visit.path.as_bytes()[i + 1..]),
+                _ => visit.path.as_os_str(),
+            };
+            let meta = if visit.kind.is_none() || self.filter.needs_meta() {
+                match entry_meta(&visit.path, followlinks) {
+                    Ok(m) => Some(m),
+                    Err(e) => return Some(Err((e, visit.path))),
+                }
+            } else {
+                None
+            };
+            if visit.depth == 0 {
+                self.top_dev = meta.as_ref().map(|m| m.dev());
+            }
+            let (is_dir, is_symlink) = match (visit.kind, meta.as_ref()) {
+                (Some(kind), _) => kind,
+                (None, Some(m)) => (m.is_dir(), false),
+                (None, None) => (false, false),
+            };
+            let matched = self.filter.lists(visit.depth) && self.filter.is_match(name, meta.as_ref(), self.now);
+            let descend = is_dir && (followlinks || !is_symlink) && self.filter.descends(visit.depth, meta.as_ref(), self.top_dev);
+            if descend {
+                if let Err(e) = self.push_children(&visit) {
+                    // like find, the directory comes before its error
+                    if !matched {
+                        return Some(Err((e, visit.path)));
+                    }
+                    self.pending_error = Some((e, visit.path.clone()));
+                }
+            }
+            if matched {
+                return Some(Ok(visit.path));
+            }
+        }
+        None
+    }
+
+    /// Queue the entries of `visit`: files first, then the directories.
+    fn push_children(&mut self, visit: &Visit) -> io::Result<()> {
+        let followlinks = self.pool.followlinks();
+        let scan = self.pool.take(&visit.path)?;
+        let ancestors = match (followlinks, scan.id) {
+            (true, Some(id)) => {
+                if Ancestor::contains(&visit.ancestors, id) {
+                    return Ok(());
+                }
+                Ancestor::push(visit.ancestors.clone(), id)
+            }
+            _ => visit.ancestors.clone(),
+        };
+        let depth = visit.depth + 1;
+        if self.filter.maxdepth.map_or(true, |m| depth < m) {
+            let prefetch = scan.dirs.iter().filter(|(_, is_symlink)| followlinks || !is_symlink);
+            self.pool.request(prefetch.map(|(name, _)| join_name(&visit.path, name)).collect::<Vec<_>>());
+        }
+        for (name, is_symlink) in scan.dirs.into_iter().rev() {
+            let path = join_name(&visit.path, &name);
+            self.stack.push(Visit { path, depth, kind: Some((true, is_symlink)), ancestors: ancestors.clone() });
+        }
+        for name in scan.files.into_iter().rev() {
+            let path = join_name(&visit.path, &name);
+            self.stack.push(Visit { path, depth, kind: Some((false, false)), ancestors: ancestors.clone() });
+        }
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl Find {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
+        let py = slf.py();
+        loop {
+            let this: &mut Find = &mut slf;
+            let next = py.allow_threads(|| this.next_path());
+            match next {
+                Some(Ok(path)) => return Ok(Some(os2py(py, &path, slf.is_bytes))),
+                Some(Err((e, path))) => {
+                    if let Some(ref onerror) = slf.onerror {
+                        onerror.call1(py, (oserror(&e, &path).value(py),))?;
+                    }
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl Drop for Find {
+    fn drop(&mut self) {
+        self.pool.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_age, parse_size, parse_types, Cmp};
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_size(">1M"), Ok((Cmp::Gt, 1, 1048576)));
+        assert_eq!(parse_size("-10k"), Ok((Cmp::Lt, 10, 1024)));
+        assert_eq!(parse_size("512c"), Ok((Cmp::Eq, 512, 1)));
+        assert_eq!(parse_size("8"), Ok((Cmp::Eq, 8, 512)));
+        assert!(parse_size(">1Q").is_err());
+        assert!(parse_size("1.5k").is_err());
+        assert_eq!(parse_age("+7"), Ok((Cmp::Gt, 7.0, 86400.0)));
+        assert_eq!(parse_age("<2h"), Ok((Cmp::Lt, 2.0, 3600.0)));
+        assert_eq!(parse_types("f,d"), Ok(vec!['f', 'd']));
+        assert!(parse_types("x").is_err());
+    }
+}
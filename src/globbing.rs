@@ -0,0 +1,232 @@
+use std::ffi::{OsStr, OsString};
+use std::iter;
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+
+use pyo3::prelude::*;
+
+use crate::_split;
+use crate::at::{isdir_at, is_dir_mode, lexists_at, stat_at, Dir, EntryType};
+use crate::ignore::Ignore;
+use crate::pattern::Segment;
+use crate::walkdir::{join_name, os2py};
+
+pub type PathIter = Box<dyn Iterator<Item = OsString> + Send>;
+
+pub struct GlobOptions {
+    pub dir_fd: Option<RawFd>,
+    pub recursive: bool,
+    pub include_hidden: bool,
+    pub ignore: Option<Arc<Ignore>>,
+}
+
+#[inline(always)]
+pub fn has_magic(s: &str) -> bool {
+    s.bytes().any(|c| c == b'*' || c == b'?' || c == b'[')
+}
+
+#[inline(always)]
+fn is_hidden(name: &OsStr) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
+
+/// `glob._join`: join unless one side is empty.
+fn join(dirname: &OsStr, basename: &OsStr) -> OsString {
+    if dirname.is_empty() {
+        basename.to_os_string()
+    } else if basename.is_empty() {
+        dirname.to_os_string()
+    } else {
+        join_name(dirname, basename)
+    }
+}
+
+/// True when `opts.ignore` excludes `path`; `is_dir` is looked up when `None`.
+fn is_ignored(opts: &GlobOptions, path: &OsStr, is_dir: Option<bool>) -> bool {
+    match opts.ignore {
+        Some(ref ignore) => ignore.is_ignored(&path.to_string_lossy(), is_dir),
+        None => false,
+    }
+}
+
+fn listdir(opts: &GlobOptions, dirname: &OsStr, dironly: bool) -> Vec<OsString> {
+    if is_ignored(opts, dirname, Some(true)) {
+        return vec![];
+    }
+    let mut dir = match Dir::open(opts.dir_fd, dirname) {
+        Ok(d) => d,
+        Err(_) => return vec![],
+    };
+    let entry_filter = opts.ignore.as_ref().and_then(|x| x.entry_filter(dirname));
+    let mut names = vec![];
+    while let Some(entry) = dir.next_entry() {
+        let (name, entry_type) = match entry {
+            Ok(v) => v,
+            Err(_) => break,
+        };
+        if dironly && !dir.entry_is_dir(&name, entry_type) {
+            continue;
+        }
+        if let Some(ref ignored) = entry_filter {
+            let is_dir = match entry_type {
+                EntryType::Unknown => stat_at(Some(dir.fd()), &name, false).map(|st| is_dir_mode(st.st_mode)).unwrap_or(false),
+                t => t == EntryType::Dir,
+            };
+            if ignored(&name, is_dir) {
+                continue;
+            }
+        }
+        names.push(name);
+    }
+    names
+}
+
+/// Defer `f` until the first item is pulled, so directories are only listed
+/// when a consumer actually gets there.
+fn lazy<F>(f: F) -> PathIter
+where
+    F: FnOnce() -> PathIter + Send + 'static,
+{
+    Box::new(iter::once_with(f).flatten())
+}
+
+fn glob0(opts: Arc<GlobOptions>, dirname: OsString, basename: String) -> PathIter {
+    lazy(move || {
+        let found = if basename.is_empty() {
+            isdir_at(opts.dir_fd, &dirname) && !is_ignored(&opts, &dirname, Some(true))
+        } else {
+            let path = join(&dirname, OsStr::new(&basename));
+            lexists_at(opts.dir_fd, &path) && !is_ignored(&opts, &path, None)
+        };
+        if found {
+            Box::new(iter::once(OsString::from(basename)))
+        } else {
+            Box::new(iter::empty())
+        }
+    })
+}
+
+fn glob1(opts: Arc<GlobOptions>, dirname: OsString, pattern: String, dironly: bool) -> PathIter {
+    lazy(move || {
+        let segment = Segment::compile(&pattern, true);
+        let show_hidden = opts.include_hidden || pattern.starts_with('.');
+        let names = listdir(&opts, &dirname, dironly)
+            .into_iter()
+            .filter(move |x| (show_hidden || !is_hidden(x)) && segment.is_match(&x.to_string_lossy()));
+        Box::new(names)
+    })
+}
+
+fn glob2(opts: Arc<GlobOptions>, dirname: OsString, dironly: bool) -> PathIter {
+    lazy(move || {
+        if is_ignored(&opts, &dirname, Some(true)) {
+            return Box::new(iter::empty());
+        }
+        let head: PathIter = if dirname.is_empty() || isdir_at(opts.dir_fd, &dirname) {
+            Box::new(iter::once(OsString::new()))
+        } else {
+            Box::new(iter::empty())
+        };
+        Box::new(head.chain(rlistdir(opts, dirname, dironly)))
+    })
+}
+
+fn rlistdir(opts: Arc<GlobOptions>, dirname: OsString, dironly: bool) -> PathIter {
+    lazy(move || {
+        let names = listdir(&opts, &dirname, dironly);
+        let include_hidden = opts.include_hidden;
+        Box::new(names.into_iter().filter(move |x| include_hidden || !is_hidden(x)).flat_map(move |x| {
+            let path = join(&dirname, &x);
+            let prefix = x.clone();
+            iter::once(x).chain(rlistdir(opts.clone(), path, dironly).map(move |y| join(&prefix, &y)))
+        }))
+    })
+}
+
+/// Lazily expand `pathname` relative to `root_dir`, mirroring CPython's
+/// `glob._iglob`.
+pub fn iglob_inner(opts: Arc<GlobOptions>, pathname: String, root_dir: OsString, dironly: bool) -> PathIter {
+    let (dirname, basename) = _split(pathname.as_str()).unwrap();
+    let (dirname, basename) = (dirname.to_string(), basename.to_string());
+    if !has_magic(&pathname) {
+        return lazy(move || {
+            let found = if basename.is_empty() {
+                let path = join(&root_dir, OsStr::new(&dirname));
+                isdir_at(opts.dir_fd, &path) && !is_ignored(&opts, &path, Some(true))
+            } else {
+                let path = join(&root_dir, OsStr::new(&pathname));
+                lexists_at(opts.dir_fd, &path) && !is_ignored(&opts, &path, None)
+            };
+            if found {
+                Box::new(iter::once(OsString::from(pathname)))
+            } else {
+                Box::new(iter::empty())
+            }
+        });
+    }
+    if dirname.is_empty() {
+        if opts.recursive && basename == "**" {
+            return glob2(opts, root_dir, dironly);
+        }
+        return glob1(opts, root_dir, basename, dironly);
+    }
+    let dirs: PathIter = if dirname != pathname && has_magic(&dirname) {
+        iglob_inner(opts.clone(), dirname, root_dir.clone(), true)
+    } else {
+        Box::new(iter::once(OsString::from(dirname)))
+    };
+    let magic = has_magic(&basename);
+    Box::new(dirs.flat_map(move |d| {
+        let dirpath = join(&root_dir, &d);
+        let names = if !magic {
+            glob0(opts.clone(), dirpath, basename.clone())
+        } else if opts.recursive && basename == "**" {
+            glob2(opts.clone(), dirpath, dironly)
+        } else {
+            glob1(opts.clone(), dirpath, basename.clone(), dironly)
+        };
+        names.map(move |n| join_name(&d, &n))
+    }))
+}
+
+pub fn iglob(opts: GlobOptions, pathname: String, root_dir: OsString) -> PathIter {
+    let skip_empty = pathname.is_empty() || (opts.recursive && pathname.starts_with("**"));
+    let it = iglob_inner(Arc::new(opts), pathname, root_dir, false);
+    if !skip_empty {
+        return it;
+    }
+    let mut it = it.peekable();
+    lazy(move || {
+        if it.peek().map(|x| x.is_empty()).unwrap_or(false) {
+            it.next();
+        }
+        Box::new(it)
+    })
+}
+
+#[pyclass(module = "fpath._fpath")]
+pub struct Glob {
+    it: Option<PathIter>,
+    is_bytes: bool,
+}
+
+impl Glob {
+    pub fn new(it: PathIter, is_bytes: bool) -> Self {
+        Glob { it: Some(it), is_bytes }
+    }
+}
+
+#[pymethods]
+impl Glob {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> Option<PyObject> {
+        let py = slf.py();
+        let mut it = slf.it.take()?;
+        let (next, it) = py.allow_threads(move || (it.next(), it));
+        slf.it = Some(it);
+        next.map(|x| os2py(py, &x, slf.is_bytes))
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use pyo3::types::PyList;
+
+use crate::_split_parts;
+use crate::globbing::has_magic;
+use crate::pattern::{PathPattern, Segment};
+use crate::utils::pyobj2str;
+
+enum Matcher {
+    Path(PathPattern),
+    Flat(Segment),
+}
+
+impl Matcher {
+    fn is_match(&self, path_str: &str) -> bool {
+        match self {
+            Matcher::Path(p) => p.full_match(path_str),
+            Matcher::Flat(s) => s.is_match(path_str),
+        }
+    }
+}
+
+/// Many glob patterns compiled into one matcher. Plain paths and `*.ext`
+/// style patterns are answered by hash lookups, the rest are tried in turn.
+pub struct GlobSetMatcher {
+    case_sensitive: bool,
+    literal_separator: bool,
+    literals: HashMap<String, Vec<usize>>,
+    extensions: HashMap<String, Vec<usize>>,
+    others: Vec<(usize, Matcher)>,
+    len: usize,
+}
+
+impl GlobSetMatcher {
+    pub fn new(patterns: &[String], case_sensitive: bool, literal_separator: bool) -> Self {
+        let mut set = GlobSetMatcher {
+            case_sensitive,
+            literal_separator,
+            literals: HashMap::new(),
+            extensions: HashMap::new(),
+            others: vec![],
+            len: patterns.len(),
+        };
+        for (i, pattern) in patterns.iter().enumerate() {
+            if !has_magic(pattern) {
+                let key = set.literal_key(pattern);
+                set.literals.entry(key).or_default().push(i);
+                continue;
+            }
+            if let Some(ext) = set.extension_of(pattern) {
+                let ext = set.fold(ext);
+                set.extensions.entry(ext).or_default().push(i);
+                continue;
+            }
+            let matcher = if literal_separator {
+                Matcher::Path(PathPattern::compile(pattern, case_sensitive))
+            } else {
+                Matcher::Flat(Segment::compile(pattern, case_sensitive))
+            };
+            set.others.push((i, matcher));
+        }
+        set
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    fn fold(&self, s: &str) -> String {
+        if self.case_sensitive {
+            s.to_string()
+        } else {
+            s.to_lowercase()
+        }
+    }
+
+    fn literal_key(&self, path_str: &str) -> String {
+        if !self.literal_separator {
+            return self.fold(path_str);
+        }
+        let (anchor, parts) = _split_parts(path_str);
+        let mut key = anchor.to_string();
+        key.push_str(parts.join("/").as_str());
+        self.fold(key.as_str())
+    }
+
+    /// The extension (with its leading dot) of patterns that only test the
+    /// end of the file name: `**/*.ext`, or `*.ext` when `*` crosses `/`.
+    fn extension_of<'a>(&self, pattern: &'a str) -> Option<&'a str> {
+        let ext = if self.literal_separator {
+            pattern.strip_prefix("**/*")?
+        } else {
+            pattern.strip_prefix('*')?
+        };
+        if ext.starts_with('.') && !has_magic(ext) && !ext.contains('/') {
+            Some(ext)
+        } else {
+            None
+        }
+    }
+
+    /// Indices of all patterns matching `path_str`, in ascending order.
+    pub fn matches(&self, path_str: &str) -> Vec<usize> {
+        let mut ret = vec![];
+        if let Some(v) = self.literals.get(&self.literal_key(path_str)) {
+            ret.extend_from_slice(v);
+        }
+        if !self.extensions.is_empty() {
+            let name = if self.literal_separator {
+                _split_parts(path_str).1.last().copied().unwrap_or("")
+            } else {
+                path_str
+            };
+            let name = self.fold(name);
+            for (i, _) in name.match_indices('.') {
+                if let Some(v) = self.extensions.get(&name[i..]) {
+                    ret.extend_from_slice(v);
+                }
+            }
+        }
+        for (i, matcher) in self.others.iter() {
+            if matcher.is_match(path_str) {
+                ret.push(*i);
+            }
+        }
+        ret.sort_unstable();
+        ret.dedup();
+        ret
+    }
+}
+
+#[pyclass(module = "fpath._fpath")]
+pub struct GlobSet {
+    matcher: GlobSetMatcher,
+}
+
+fn path_arg(py: Python, path: &PyAny) -> PyResult<String> {
+    Ok(pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?.0)
+}
+
+fn path_list_arg(py: Python, paths: &PyAny) -> PyResult<Vec<String>> {
+    let mut ret = vec![];
+    for path in paths.iter()? {
+        ret.push(path_arg(py, path?)?);
+    }
+    Ok(ret)
+}
+
+#[pymethods]
+impl GlobSet {
+    #[new]
+    #[pyo3(signature = (patterns, case_sensitive=true, literal_separator=true))]
+    fn new(py: Python, patterns: &PyAny, case_sensitive: bool, literal_separator: bool) -> PyResult<Self> {
+        let patterns = path_list_arg(py, patterns)?;
+        if patterns.iter().any(|x| x.is_empty()) {
+            return Err(exceptions::PyValueError::new_err("empty pattern"));
+        }
+        let matcher = py.allow_threads(|| GlobSetMatcher::new(&patterns, case_sensitive, literal_separator));
+        Ok(GlobSet { matcher })
+    }
+
+    fn __len__(&self) -> usize {
+        self.matcher.len()
+    }
+
+    fn is_match(&self, py: Python, path: &PyAny) -> PyResult<bool> {
+        Ok(!self.matcher.matches(path_arg(py, path)?.as_str()).is_empty())
+    }
+
+    fn matches(&self, py: Python, path: &PyAny) -> PyResult<Vec<usize>> {
+        Ok(self.matcher.matches(path_arg(py, path)?.as_str()))
+    }
+
+    fn first(&self, py: Python, path: &PyAny) -> PyResult<Option<usize>> {
+        Ok(self.matcher.matches(path_arg(py, path)?.as_str()).first().copied())
+    }
+
+    fn last(&self, py: Python, path: &PyAny) -> PyResult<Option<usize>> {
+        Ok(self.matcher.matches(path_arg(py, path)?.as_str()).last().copied())
+    }
+
+    fn matches_many(&self, py: Python, paths: &PyAny) -> PyResult<PyObject> {
+        let paths = path_list_arg(py, paths)?;
+        let ret: Vec<Vec<usize>> = py.allow_threads(|| paths.iter().map(|x| self.matcher.matches(x)).collect());
+        Ok(PyList::new(py, ret.into_iter().map(|x| PyList::new(py, x))).to_object(py))
+    }
+
+    fn first_many(&self, py: Python, paths: &PyAny) -> PyResult<Vec<Option<usize>>> {
+        let paths = path_list_arg(py, paths)?;
+        Ok(py.allow_threads(|| paths.iter().map(|x| self.matcher.matches(x).first().copied()).collect()))
+    }
+
+    fn last_many(&self, py: Python, paths: &PyAny) -> PyResult<Vec<Option<usize>>> {
+        let paths = path_list_arg(py, paths)?;
+        Ok(py.allow_threads(|| paths.iter().map(|x| self.matcher.matches(x).last().copied()).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GlobSetMatcher;
+
+    fn globset(patterns: &[&str], case_sensitive: bool, literal_separator: bool) -> GlobSetMatcher {
+        let patterns: Vec<String> = patterns.iter().map(|x| x.to_string()).collect();
+        GlobSetMatcher::new(&patterns, case_sensitive, literal_separator)
+    }
+
+    #[test]
+    fn matches() {
+        let set = globset(&["**/*.rs", "src/lib.rs", "src/*", "docs/**", "*.md", "**/*.tar.gz"], true, true);
+        assert_eq!(set.matches("src/lib.rs"), vec![0, 1, 2]);
+        assert_eq!(set.matches("src//lib.rs"), vec![0, 1, 2]);
+        assert_eq!(set.matches("src/a/b.rs"), vec![0]);
+        assert_eq!(set.matches("docs/a/README.md"), vec![3]);
+        assert_eq!(set.matches("README.md"), vec![4]);
+        assert_eq!(set.matches("dist/a.tar.gz"), vec![5]);
+        assert!(set.matches("a.gz").is_empty());
+    }
+
+    #[test]
+    fn options() {
+        let set = globset(&["*.md", "src/*.RS"], false, false);
+        assert_eq!(set.matches("docs/README.MD"), vec![0]);
+        assert_eq!(set.matches("src/a/lib.rs"), vec![1]);
+        let set = globset(&["*.md", "src/*.RS"], true, true);
+        assert!(set.matches("docs/README.md").is_empty());
+        assert!(set.matches("src/lib.rs").is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+//! `.gitignore` style matching: rules are read from ignore files in each
+//! directory below a root and apply to the paths under that directory.
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::sync::{Arc, Mutex};
+
+use pyo3::exceptions;
+use pyo3::prelude::*;
+
+use crate::pattern::PathPattern;
+use crate::utils::pyobj2str;
+use crate::{_abspath, _inner_join};
+
+pub const IGNORE_FILENAMES: &[&str] = &[".gitignore", ".ignore"];
+
+struct Rule {
+    pattern: PathPattern,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// Replace backslash escapes with something `Segment` reads literally:
+/// a one-character class for the glob metacharacters.
+fn unescape(pat: &str) -> String {
+    let mut ret = String::with_capacity(pat.len());
+    let mut chars = pat.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(e @ ('*' | '?' | '[')) => {
+                    ret.push('[');
+                    ret.push(e);
+                    ret.push(']');
+                }
+                Some(e) => ret.push(e),
+                None => {}
+            },
+            '[' if chars.as_str().starts_with('^') => {
+                ret.push_str("[!");
+                chars.next();
+            }
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+impl Rule {
+    /// Parse one line of an ignore file, `None` for blanks and comments.
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let mut line = line;
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+        let mut pattern = unescape(line);
+        if !anchored {
+            pattern.insert_str(0, "**/");
+        }
+        // `dir/**` matches everything inside `dir`, but not `dir` itself
+        if pattern.ends_with("/**") {
+            pattern.push_str("/*");
+        }
+        Some(Rule { pattern: PathPattern::compile(&pattern, true), negated, dir_only })
+    }
+
+    fn is_match(&self, rel: &str, is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && self.pattern.full_match(rel)
+    }
+}
+
+/// Rules from the ignore files of one directory, in increasing precedence.
+struct DirRules {
+    rules: Vec<Rule>,
+}
+
+impl DirRules {
+    fn parse(text: &str) -> Vec<Rule> {
+        text.lines().filter_map(Rule::parse).collect()
+    }
+
+    /// `Some(true)` when the last matching rule ignores `rel`, `Some(false)`
+    /// when it re-includes it and `None` when no rule matches.
+    fn matched(&self, rel: &str, is_dir: bool) -> Option<bool> {
+        self.rules.iter().rev().find(|x| x.is_match(rel, is_dir)).map(|x| !x.negated)
+    }
+}
+
+pub struct Ignore {
+    root: String,
+    filenames: Vec<String>,
+    base: DirRules,
+    dirs: Mutex<HashMap<String, Arc<DirRules>>>,
+}
+
+impl Ignore {
+    pub fn new(root: String, patterns: &[String], filenames: Vec<String>) -> Self {
+        let base = DirRules { rules: patterns.iter().filter_map(|x| Rule::parse(x)).collect() };
+        Ignore { root, filenames, base, dirs: Mutex::new(HashMap::new()) }
+    }
+
+    /// `path` relative to the root, `""` for the root itself and `None` for
+    /// paths outside of it.
+    fn relative(&self, path: &str) -> Option<String> {
+        let abs = _abspath(path).ok()?;
+        if abs == self.root {
+            return Some(String::new());
+        }
+        let rest = abs.strip_prefix(self.root.as_str())?;
+        let rest = if self.root.ends_with('/') { Some(rest) } else { rest.strip_prefix('/') };
+        rest.map(|x| x.to_string())
+    }
+
+    fn dir_rules(&self, rel_dir: &str) -> Arc<DirRules> {
+        if let Some(rules) = self.dirs.lock().unwrap_or_else(|e| e.into_inner()).get(rel_dir) {
+            return rules.clone();
+        }
+        let dir = _inner_join(&self.root, &[rel_dir]);
+        let mut rules = vec![];
+        for name in self.filenames.iter() {
+            if let Ok(bytes) = std::fs::read(_inner_join(&dir, &[name])) {
+                rules.extend(DirRules::parse(&String::from_utf8_lossy(&bytes)));
+            }
+        }
+        let rules = Arc::new(DirRules { rules });
+        self.dirs.lock().unwrap_or_else(|e| e.into_inner()).insert(rel_dir.to_string(), rules.clone());
+        rules
+    }
+
+    /// Decide `rel` alone, assuming none of its parent directories is
+    /// ignored: the ignore file nearest to it wins, then the root patterns.
+    fn is_ignored_rel(&self, rel: &str, is_dir: bool) -> bool {
+        let mut end = rel.len();
+        while let Some(i) = rel[..end].rfind('/') {
+            end = i;
+            let sub = &rel[i + 1..];
+            if let Some(ignored) = self.dir_rules(&rel[..i]).matched(sub, is_dir) {
+                return ignored;
+            }
+        }
+        if let Some(ignored) = self.dir_rules("").matched(rel, is_dir) {
+            return ignored;
+        }
+        self.base.matched(rel, is_dir).unwrap_or(false)
+    }
+
+    /// Whether `path` is ignored, either by itself or because one of its
+    /// parent directories is. Paths outside of the root are never ignored.
+    pub fn is_ignored(&self, path: &str, is_dir: Option<bool>) -> bool {
+        let rel = match self.relative(path) {
+            Some(r) if !r.is_empty() => r,
+            _ => return false,
+        };
+        for (i, _) in rel.match_indices('/') {
+            if self.is_ignored_rel(&rel[..i], true) {
+                return true;
+            }
+        }
+        let is_dir = is_dir.unwrap_or_else(|| std::fs::symlink_metadata(path).map(|m| m.is_dir()).unwrap_or(false));
+        self.is_ignored_rel(&rel, is_dir)
+    }
+
+    /// A filter for the entries of `dirpath`, whose own ancestors are known
+    /// not to be ignored. `None` when `dirpath` is outside of the root.
+    pub fn entry_filter(&self, dirpath: &OsStr) -> Option<impl Fn(&OsStr, bool) -> bool + '_> {
+        let rel_dir = self.relative(&dirpath.to_string_lossy())?;
+        Some(move |name: &OsStr, is_dir: bool| {
+            let name = name.to_string_lossy();
+            let rel = if rel_dir.is_empty() { name.into_owned() } else { format!("{}/{}", rel_dir, name) };
+            self.is_ignored_rel(&rel, is_dir)
+        })
+    }
+}
+
+#[pyclass(module = "fpath._fpath")]
+pub struct IgnoreMatcher {
+    pub ignore: Arc<Ignore>,
+}
+
+#[pymethods]
+impl IgnoreMatcher {
+    #[new]
+    #[pyo3(signature = (root, patterns=None, filenames=None))]
+    fn new(py: Python, root: &PyAny, patterns: Option<&PyAny>, filenames: Option<&PyAny>) -> PyResult<Self> {
+        let (root_str, _) = pyobj2str(&py, root).map_err(exceptions::PyTypeError::new_err)?;
+        let mut lines = vec![];
+        if let Some(patterns) = patterns {
+            for x in patterns.iter()? {
+                lines.push(pyobj2str(&py, x?).map_err(exceptions::PyTypeError::new_err)?.0);
+            }
+        }
+        let filenames = match filenames {
+            Some(names) => {
+                let mut ret = vec![];
+                for x in names.iter()? {
+                    ret.push(pyobj2str(&py, x?).map_err(exceptions::PyTypeError::new_err)?.0);
+                }
+                ret
+            }
+            None => IGNORE_FILENAMES.iter().map(|x| x.to_string()).collect(),
+        };
+        let ignore = Ignore::new(_abspath(&root_str)?, &lines, filenames);
+        Ok(IgnoreMatcher { ignore: Arc::new(ignore) })
+    }
+
+    #[getter]
+    fn root(&self) -> String {
+        self.ignore.root.clone()
+    }
+
+    #[pyo3(signature = (path, is_dir=None))]
+    fn is_ignored(&self, py: Python, path: &PyAny, is_dir: Option<bool>) -> PyResult<bool> {
+        let (path_str, _) = pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?;
+        let ignore = self.ignore.clone();
+        Ok(py.allow_threads(move || ignore.is_ignored(&path_str, is_dir)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unescape, Rule};
+
+    fn is_match(line: &str, rel: &str, is_dir: bool) -> bool {
+        Rule::parse(line).unwrap().is_match(rel, is_dir)
+    }
+
+    #[test]
+    fn parse() {
+        assert!(Rule::parse("").is_none());
+        assert!(Rule::parse("# comment").is_none());
+        assert!(Rule::parse("/").is_none());
+        assert!(Rule::parse("!keep").unwrap().negated);
+        assert!(!Rule::parse("\\!keep").unwrap().negated);
+        assert!(Rule::parse("build/").unwrap().dir_only);
+        assert_eq!(unescape("\\*.o"), "[*].o");
+        assert_eq!(unescape("[^a]"), "[!a]");
+        assert_eq!(unescape("\\#x"), "#x");
+    }
+
+    #[test]
+    fn rule_match() {
+        assert!(is_match("*.o", "a/b/c.o", false));
+        assert!(is_match("build/", "a/build", true));
+        assert!(!is_match("build/", "a/build", false));
+        assert!(is_match("/build", "build", false));
+        assert!(!is_match("/build", "a/build", false));
+        assert!(is_match("doc/*.txt", "doc/a.txt", false));
+        assert!(!is_match("doc/*.txt", "doc/a/b.txt", false));
+        assert!(is_match("doc/**/*.txt", "doc/a/b.txt", false));
+        assert!(is_match("**/foo", "a/foo", false));
+        assert!(is_match("foo/**", "foo/a/b", false));
+        assert!(!is_match("foo/**", "foo", true));
+        assert!(is_match("trailing \\ ", "trailing  ", false));
+        assert!(is_match("trailing   ", "trailing", false));
+    }
+}
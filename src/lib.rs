@@ -4,9 +4,12 @@ extern crate shellexpand;
 use std::{env, str};
 use std::collections::HashMap;
 use std::env::current_dir;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, MAIN_SEPARATOR};
 use pyo3::prelude::*;
-use pyo3::types::{PyBytes, PyDict, PyString, PyTuple};
+use pyo3::types::{PyBytes, PyDict, PyList, PyString, PyTuple};
 use pyo3::exceptions;
 use uzers::os::unix::UserExt;
 use uzers::{get_user_by_uid, get_user_by_name, get_current_uid};
@@ -14,97 +17,267 @@ use uzers::{get_user_by_uid, get_user_by_name, get_current_uid};
 #[macro_use]
 mod utils;
 use utils::pyobj2str;
+mod pathiter;
+use pathiter::{Ancestors, Components};
+mod pattern;
+mod walkdir;
+use walkdir::Walk;
+mod at;
+mod globbing;
+use globbing::{Glob, GlobOptions};
+#[allow(unknown_lints, non_local_definitions)]
+mod globset;
+use globset::GlobSet;
+#[allow(unknown_lints, non_local_definitions)]
+mod ignore;
+use ignore::IgnoreMatcher;
+#[allow(unknown_lints, non_local_definitions)]
+mod finder;
+use finder::{Find, FindFilter};
+mod dirent;
+use dirent::DirEntry;
+mod du;
+mod pathsearch;
+#[allow(unknown_lints, non_local_definitions)]
+mod rpcache;
+mod vlinks;
+#[cfg(target_os = "linux")]
+mod beneath;
+use rpcache::{LinkSource, PrefixCache, RealpathCache};
+use vlinks::VirtualLinks;
 
 const SEP: u8 = MAIN_SEPARATOR as u8;
 
 
+/// `(st_dev, st_ino)` identity of a file, as compared by `os.path.samestat`.
+#[inline(always)]
+fn _file_id(meta: &std::fs::Metadata) -> (u64, u64) {
+    (meta.dev(), meta.ino())
+}
+
 #[inline(always)]
 fn _islink(path_str: &str) -> bool {
     std::fs::read_link(path_str).is_ok()
 }
 
-fn _joinrealpath(path_str: &str, rest: &str, strict: bool, seen: &HashMap<String, Option<String>>) -> Result<(String, bool), PyErr> {
-    let mut use_seen = seen.clone();
-    let (mut ret_path, mut use_rest) = if _isabs(rest) {
-        let (_head, tail) = rest.split_at(1);
-        (MAIN_SEPARATOR.to_string(), tail)
-    } else {
-        (path_str.to_string(), rest)
+/// One step `_joinrealpath` took: what it did (`component`, `parent`,
+/// `missing`, `symlink`, `resolved`, `seen` or `loop`), the path it looked
+/// at, the raw link text for symlinks, and the path resolved so far.
+struct TraceStep {
+    action: &'static str,
+    path: String,
+    target: Option<String>,
+    resolved: String,
+}
+
+#[inline(always)]
+fn _trace<F: FnOnce() -> TraceStep>(trace: &mut Option<&mut Vec<TraceStep>>, step: F) {
+    if let Some(t) = trace {
+        t.push(step());
+    }
+}
+
+/// `UnicodeDecodeError` for a symlink target that isn't valid UTF-8.
+fn _utf8_error(bytes: &[u8]) -> PyErr {
+    let (start, end) = match str::from_utf8(bytes) {
+        Ok(_) => (0, bytes.len()),
+        Err(e) => (e.valid_up_to(), e.valid_up_to() + e.error_len().unwrap_or(1)),
     };
+    let bytes: PyObject = Python::with_gil(|py| PyBytes::new(py, bytes).into());
+    exceptions::PyUnicodeDecodeError::new_err(("utf-8", bytes, start, end, "invalid utf-8 in symlink target"))
+}
+
+/// Symlinks followed in one resolution before giving up, like the kernel's
+/// `MAXSYMLINKS`.
+const MAX_SYMLINKS: usize = 40;
+
+/// Work left for `_joinrealpath`: a component, as a byte range into one of
+/// the path strings, or the marker that the link at `seen` key `.0` has
+/// been fully resolved once everything pushed after it is done.
+enum RealpathPart {
+    Name(usize, usize, usize),
+    Resolved(String),
+}
+
+/// Push the components of `sources[idx]` so they pop off in order.
+fn _push_parts(stack: &mut Vec<RealpathPart>, sources: &[String], idx: usize) {
+    let src = sources[idx].as_bytes();
+    let mut end = src.len();
+    for i in memchr::memrchr_iter(MAIN_SEPARATOR as u8, src) {
+        stack.push(RealpathPart::Name(idx, i + 1, end));
+        end = i;
+    }
+    stack.push(RealpathPart::Name(idx, 0, end));
+}
+
+/// `_inner_join(path, &[name])` in place, for a relative `name`.
+#[inline(always)]
+fn _push_name(path: &mut String, name: &str) {
+    if !path.is_empty() && !path.ends_with(MAIN_SEPARATOR) {
+        path.push(MAIN_SEPARATOR);
+    }
+    path.push_str(name);
+}
+
+/// Drop the last component of `path` in place, the way `_joinrealpath`
+/// always handled `..`: a leading run of `..` grows instead.
+fn _pop_name(path: &mut String) {
+    if path.is_empty() {
+        path.push_str("..");
+        return;
+    }
+    let start = memchr::memrchr(MAIN_SEPARATOR as u8, path.as_bytes()).map_or(0, |i| i + 1);
+    let pardir = &path[start..] == "..";
+    path.truncate(start);
+    let trimmed = path.trim_end_matches(MAIN_SEPARATOR).len();
+    if trimmed > 0 {
+        path.truncate(trimmed);
+    }
+    if pardir {
+        _push_name(path, "..");
+        _push_name(path, "..");
+    }
+}
 
-    while !use_rest.is_empty() {
-        let (name, _, tmp_rest) = partition!(use_rest, "/");
-        use_rest = tmp_rest;
+/// `root`, when not empty, is where `/` is on the host: paths are resolved
+/// as if chrooted there and only touch the filesystem with it prefixed.
+/// Every step is appended to `trace` when given.
+///
+/// Symlink targets are pushed onto a stack of pending components rather
+/// than resolved recursively, with one `seen` table for the whole call.
+/// The flag is false when a loop, or more than `MAX_SYMLINKS` links, cut
+/// resolution short; the rest of the path is then appended unresolved.
+///
+/// With a cache, every prefix resolved along the way is handed to it at
+/// the end, up to the first component that couldn't be read, and prefixes
+/// it already holds are taken from it without touching the disk.
+fn _joinrealpath(path_str: &str, rest: &str, strict: bool, source: LinkSource, root: &str, mut trace: Option<&mut Vec<TraceStep>>) -> Result<(String, bool), PyErr> {
+    let mut host = String::from(root);
+    let mut ret_path = String::with_capacity(path_str.len() + rest.len());
+    ret_path.push_str(if _isabs(rest) { "/" } else { path_str });
+    let mut seen: HashMap<String, Option<String>> = HashMap::new();
+    let mut sources = vec![rest.to_string()];
+    let mut stack = vec![];
+    _push_parts(&mut stack, &sources, 0);
+    let mut hops = 0;
+    let mut learned = vec![];
+    let mut learning = matches!(source, LinkSource::Fs(Some(_)));
+
+    while let Some(part) = stack.pop() {
+        let (idx, start, end) = match part {
+            RealpathPart::Name(idx, start, end) => (idx, start, end),
+            RealpathPart::Resolved(link) => {
+                _trace(&mut trace, || TraceStep { action: "resolved", path: link.clone(), target: None, resolved: ret_path.clone() });
+                if learning {
+                    learned.push((link.clone(), Some(ret_path.clone())));
+                }
+                seen.insert(link, Some(ret_path.clone()));
+                continue;
+            }
+        };
+        let name = &sources[idx][start..end];
         if name.is_empty() || name == "." {
             continue;
         }
         if name == ".." {
-            if ret_path.is_empty() {
-                ret_path = "..".to_string();
-            } else {
-                let (rp, n) = _inner_split(ret_path.as_str()).unwrap();
-                ret_path = rp;
-                if n == ".." {
-                    let rp = _inner_join(ret_path.as_str(), &["..", ".."]);
-                    ret_path = rp;
-                }
-            }
+            let prev = if trace.is_some() { _inner_join(ret_path.as_str(), &[name]) } else { String::new() };
+            _pop_name(&mut ret_path);
+            _trace(&mut trace, || TraceStep { action: "parent", path: prev, target: None, resolved: ret_path.clone() });
             continue;
         }
 
-        let newpath = _inner_join(ret_path.as_str(), &[name]);
-        let is_link = match std::fs::symlink_metadata(newpath.as_str()) {
-            Ok(meta) => {
-                meta.file_type().is_symlink()
-            },
-            Err(_) => {
-                if strict {
-                    return Err(exceptions::PyFileNotFoundError::new_err(format!("invalid path: {}", newpath)));
-                }
-                false
+        let parent_len = ret_path.len();
+        _push_name(&mut ret_path, name);
+        host.truncate(root.len());
+        host.push_str(&ret_path);
+        if let Some(resolved) = source.resolved(root, &ret_path) {
+            if let Some(v) = resolved {
+                ret_path.clear();
+                ret_path.push_str(&v);
             }
-        };
-        if !is_link {
-            ret_path = newpath;
             continue;
         }
-        if use_seen.contains_key(newpath.as_str()) {
-            match use_seen.get(newpath.as_str()).unwrap() {
-                &Some(ref v) => {
-                    ret_path = v.to_string();
-                    continue;
+        let target = match source.lookup(&host) {
+            Ok(Some(target)) => target,
+            Ok(None) => {
+                if learning {
+                    learned.push((ret_path.clone(), None));
                 }
-                _ => {}
+                _trace(&mut trace, || TraceStep { action: "component", path: ret_path.clone(), target: None, resolved: ret_path.clone() });
+                continue;
+            }
+            // like CPython, a component that can't be read (or a link
+            // replaced before `readlink`) is taken as is unless strict
+            Err(e) => {
+                if strict {
+                    return Err(walkdir::oserror(&e, OsStr::new(&host)));
+                }
+                learning = false;
+                _trace(&mut trace, || TraceStep { action: "missing", path: ret_path.clone(), target: None, resolved: ret_path.clone() });
+                continue;
+            }
+        };
+        let newpath = ret_path.clone();
+        ret_path.truncate(parent_len);
+        let looped = match seen.get(&newpath) {
+            Some(Some(v)) => {
+                _trace(&mut trace, || TraceStep { action: "seen", path: newpath.clone(), target: None, resolved: v.clone() });
+                ret_path.clear();
+                ret_path.push_str(v);
+                continue;
             }
+            Some(None) => true,
+            None => {
+                hops += 1;
+                hops > MAX_SYMLINKS
+            }
+        };
+        if looped {
             if strict {
-                if std::fs::metadata(newpath.as_str()).is_err() {
-                    return Err(exceptions::PyOSError::new_err(format!("invalid path: {}", newpath)));
+                return Err(walkdir::oserror(&source.loop_error(&host), OsStr::new(&host)));
+            }
+            // what's left of each path, innermost first, joined on as is;
+            // like `os.path.join`, a remainder starting with `/` replaces
+            // everything before it
+            ret_path.clear();
+            ret_path.push_str(&newpath);
+            let mut last = None;
+            while let Some(part) = stack.pop() {
+                if let RealpathPart::Name(idx, start, _) = part {
+                    if last == Some(idx) {
+                        continue;
+                    }
+                    last = Some(idx);
+                    let remainder = &sources[idx][start..];
+                    if remainder.starts_with(MAIN_SEPARATOR) {
+                        ret_path.clear();
+                        ret_path.push_str(remainder);
+                    } else {
+                        _push_name(&mut ret_path, remainder);
+                    }
                 }
-            } else {
-                return Ok((_inner_join(newpath.as_str(), &[use_rest]), false));
             }
+            _trace(&mut trace, || TraceStep { action: "loop", path: newpath.clone(), target: None, resolved: ret_path.clone() });
+            source.remember(root, learned);
+            return Ok((ret_path, false));
         }
-
-        use_seen.insert(newpath.clone(), None);
-        let indeep = std::fs::read_link(newpath.clone()).unwrap();
-        match _joinrealpath(
-            ret_path.as_str(),
-            indeep.to_str().unwrap(),
-            strict,
-            &use_seen.clone(),
-        ) {
-            Ok((rp, ok)) => {
-                ret_path = rp;
-                if !ok {
-                    return Ok((_inner_join(ret_path.as_str(), &[use_rest]), false));
-                }
-            },
-            Err(e) => return Err(e),
+        let target = match target.into_string() {
+            Ok(t) => t,
+            Err(t) => return Err(_utf8_error(t.as_bytes())),
         };
-        use_seen.insert(newpath, Some(ret_path.clone()));
+        _trace(&mut trace, || TraceStep { action: "symlink", path: newpath.clone(), target: Some(target.clone()), resolved: ret_path.clone() });
+        if _isabs(&target) {
+            ret_path.clear();
+            ret_path.push(MAIN_SEPARATOR);
+        }
+        seen.insert(newpath.clone(), None);
+        stack.push(RealpathPart::Resolved(newpath));
+        sources.push(target);
+        _push_parts(&mut stack, &sources, sources.len() - 1);
     }
 
-    Ok((ret_path.to_string(), true))
+    source.remember(root, learned);
+    Ok((ret_path, true))
 }
 
 pub fn _inner_join(path_str: &str, path_list: &[&str]) -> String {
@@ -237,32 +410,59 @@ fn _join(py: &Python, path_str: &str, path_list: &PyTuple, is_bytes: bool) -> Py
     str2pyobj!(*py, ret_path.as_str(), is_bytes)
 }
 
-fn _normpath(path_str: &str) -> String {
-    if path_str.is_empty() {
-        return ".".to_string();
-    }
-    let initial_slashes = path_str.starts_with(MAIN_SEPARATOR);
-    let initial_slashes_str = if initial_slashes && path_str.starts_with("//") && !path_str.starts_with("///") {
+/// Relative link text from the directory of `link_path` to `target`, with
+/// both sides resolved physically. The link itself and the last component
+/// of `target` are left alone, so a link to a symlink stays one.
+fn _symlink_target(target: &str, link_path: &str) -> Result<String, PyErr> {
+    let parent = |p: &str| {
+        let d = _dirname(p);
+        if d.is_empty() { ".".to_string() } else { d.to_string() }
+    };
+    let link_dir = _realpath(&parent(link_path), false, None, None)?;
+    let (_, name) = _inner_split(target.trim_end_matches(MAIN_SEPARATOR)).unwrap();
+    let target = if name.is_empty() || name == "." || name == ".." {
+        _realpath(target, false, None, None)?
+    } else {
+        _inner_join(&_realpath(&parent(target.trim_end_matches(MAIN_SEPARATOR)), false, None, None)?, &[&name])
+    };
+    Ok(_relpath(&target, &link_dir))
+}
+
+/// Split `path_str` into its anchor ("", "/" or "//") and its non-empty
+/// components other than ".". ".." is kept as is.
+fn _split_parts(path_str: &str) -> (&'static str, Vec<&str>) {
+    let anchor = if !path_str.starts_with(MAIN_SEPARATOR) {
+        ""
+    } else if path_str.starts_with("//") && !path_str.starts_with("///") {
         "//"
     } else {
         "/"
     };
+    let parts = path_str.split(MAIN_SEPARATOR).filter(|x| !x.is_empty() && *x != ".").collect();
+    (anchor, parts)
+}
+
+/// `_split_parts` with ".." folded the way `_normpath` does.
+fn _normparts(path_str: &str) -> (&'static str, Vec<&str>) {
+    let (anchor, parts) = _split_parts(path_str);
     let mut new_comps: Vec<&str> = vec![];
-    for comp in path_str.split('/').into_iter() {
-        if comp.is_empty() || comp == "." {
-            continue;
-        }
-        if comp != ".." || (!initial_slashes && new_comps.len() == 0)
-            || (!new_comps.is_empty() && *new_comps.last().unwrap() == "..")
-        {
+    for comp in parts {
+        if comp != ".." || (anchor.is_empty() && new_comps.is_empty()) || new_comps.last() == Some(&"..") {
             new_comps.push(comp);
-        } else if !new_comps.is_empty() {
+        } else {
             new_comps.pop();
         }
     }
+    (anchor, new_comps)
+}
 
+fn _normpath(path_str: &str) -> String {
+    if path_str.is_empty() {
+        return ".".to_string();
+    }
+    let (initial_slashes_str, new_comps) = _normparts(path_str);
     let new_comps_path = new_comps.join("/");
-    if initial_slashes {
+    if !initial_slashes_str.is_empty() {
         let mut head_sep = initial_slashes_str.to_string();
         head_sep.push_str(new_comps_path.as_str());
         head_sep
@@ -273,14 +473,103 @@ fn _normpath(path_str: &str) -> String {
     }
 }
 
-fn _realpath(path_str: &str, strict: bool) -> Result<String, PyErr> {
-    let seen = HashMap::new();
-    match _joinrealpath("", path_str, strict, &seen) {
+fn _joinall(anchor: &str, parts: &[&str]) -> String {
+    let ret_path = _inner_join(anchor, parts);
+    if ret_path.is_empty() {
+        ".".to_string()
+    } else {
+        ret_path
+    }
+}
+
+/// `ValueError` like `os.lstat` raises for paths the OS can't take.
+fn _check_nul(path_str: &str) -> Result<(), PyErr> {
+    if path_str.contains('\0') {
+        return Err(exceptions::PyValueError::new_err("embedded null byte"));
+    }
+    Ok(())
+}
+
+/// `realpath` with `root` standing in for `/`: the result is always `root`
+/// or below it, and the flag is false when a symlink loop cut resolution
+/// short.
+fn _realpath_root(path_str: &str, root: &str, strict: bool, cache: Option<&PrefixCache>) -> Result<(String, bool), PyErr> {
+    _check_nul(path_str)?;
+    _check_nul(root)?;
+    // relative paths start at `root` too; nothing is taken from the cwd
+    let root = _abspath(root)?;
+    let root = root.trim_end_matches(MAIN_SEPARATOR);
+    let (ret_path, ok) = _joinrealpath("/", path_str, strict, LinkSource::Fs(cache), root, None)?;
+    let ret_path = _normpath(&ret_path);
+    let ret_path = match ret_path.as_str() {
+        "/" if !root.is_empty() => root.to_string(),
+        p => format!("{}{}", root, p),
+    };
+    Ok((ret_path, ok))
+}
+
+/// The steps `realpath(path_str)` takes, ending with a `result` step that
+/// holds exactly what it returns.
+fn _realpath_trace(path_str: &str) -> Result<Vec<TraceStep>, PyErr> {
+    _check_nul(path_str)?;
+    let mut steps = vec![];
+    let (ret_path, _) = _joinrealpath("", path_str, false, LinkSource::Fs(None), "", Some(&mut steps))?;
+    let resolved = _abspath(ret_path.as_str())?;
+    steps.push(TraceStep { action: "result", path: ret_path, target: None, resolved });
+    Ok(steps)
+}
+
+fn _realpath(path_str: &str, strict: bool, cache: Option<&PrefixCache>, root: Option<&str>) -> Result<String, PyErr> {
+    _check_nul(path_str)?;
+    if let Some(root) = root {
+        return Ok(_realpath_root(path_str, root, strict, cache)?.0);
+    }
+    // cache entries are keyed by absolute path; the cwd is already physical
+    // so prefixing it doesn't change what relative components resolve to
+    let path_str = match cache {
+        Some(_) if !_isabs(path_str) => match current_dir() {
+            Ok(c) => _inner_join(&c.to_string_lossy(), &[path_str]),
+            Err(e) => return Err(exceptions::PyOSError::new_err(format!("{}", e))),
+        },
+        _ => path_str.to_string(),
+    };
+    match _joinrealpath("", path_str.as_str(), strict, LinkSource::Fs(cache), "", None) {
         Ok((ret_path, _)) => return _abspath(ret_path.as_str()),
         Err(e) => return Err(e),
     }
 }
 
+/// `realpath` of a relative path starting at the directory `dir_fd` refers
+/// to, looking each component up with `fstatat`/`readlinkat`. That
+/// directory's own path is only asked for when the result is still
+/// relative to it.
+fn _realpath_at(path_str: &str, strict: bool, dir_fd: i32) -> Result<String, PyErr> {
+    _check_nul(path_str)?;
+    let (ret_path, _) = _joinrealpath("", path_str, strict, LinkSource::At(dir_fd), "", None)?;
+    if _isabs(&ret_path) {
+        return Ok(_normpath(&ret_path));
+    }
+    let base = at::fd_dir_path(dir_fd).map_err(|e| walkdir::oserror(&e, OsStr::new(path_str)))?;
+    Ok(_normpath(&_inner_join(&base.to_string_lossy(), &[&ret_path])))
+}
+
+/// `realpath` against `links` instead of the filesystem. Relative paths,
+/// keys included, are taken relative to `cwd`.
+fn _resolve_virtual(path_str: &str, links: Vec<(String, String)>, existing: Option<Vec<String>>, strict: bool, cwd: &str) -> Result<String, PyErr> {
+    _check_nul(path_str)?;
+    if !_isabs(cwd) {
+        return Err(exceptions::PyValueError::new_err("cwd must be an absolute path"));
+    }
+    let cwd = _normpath(cwd);
+    let key = |p: &str| _normpath(&_inner_join(&cwd, &[p]));
+    let links = links.into_iter().map(|(k, v)| (key(&k), v)).collect();
+    let existing = existing.map(|paths| paths.iter().map(|p| key(p)).collect());
+    let vlinks = VirtualLinks::new(links, existing);
+    let path_str = _inner_join(&cwd, &[path_str]);
+    let (ret_path, _) = _joinrealpath("", &path_str, strict, LinkSource::Virtual(&vlinks), "", None)?;
+    Ok(_normpath(&ret_path))
+}
+
 fn _commonprefix(m: &Vec<&[String]>) -> Result<Vec<String>, String> {
     let s1 = m.into_iter().min().unwrap();
     let s2 = m.into_iter().max().unwrap();
@@ -318,6 +607,40 @@ fn _relpath(path_str: &str, start: &str) -> String {
     _inner_join(rel_list[0], &rel_list[1..])
 }
 
+fn _is_relative_to(path_str: &str, other: &str) -> bool {
+    let (path_anchor, path_list) = _split_parts(path_str);
+    let (other_anchor, other_list) = _split_parts(other);
+    path_anchor == other_anchor && path_list.starts_with(&other_list)
+}
+
+fn _relative_to(path_str: &str, other: &str, walk_up: bool) -> Result<String, String> {
+    let (path_anchor, path_list) = _split_parts(path_str);
+    let (other_anchor, other_list) = _split_parts(other);
+    if path_anchor != other_anchor {
+        if walk_up {
+            return Err(format!("'{}' and '{}' have different anchors", path_str, other));
+        }
+        return Err(format!("'{}' is not in the subpath of '{}'", path_str, other));
+    }
+    let path_list: Vec<String> = path_list.iter().map(|x| x.to_string()).collect();
+    let other_list: Vec<String> = other_list.iter().map(|x| x.to_string()).collect();
+    let i = _commonprefix(&vec![path_list.as_slice(), other_list.as_slice()])?.len();
+    if i < other_list.len() {
+        if !walk_up {
+            return Err(format!("'{}' is not in the subpath of '{}'", path_str, other));
+        }
+        if other_list[i..].iter().any(|x| x == "..") {
+            return Err(format!("'..' segment in '{}' cannot be walked", other));
+        }
+    }
+    let num = other_list.len() - i;
+    let rel_list: Vec<&str> = (0..num).map(|_| "..").chain(path_list[i..].iter().map(|x| x.as_str())).collect();
+    if rel_list.is_empty() {
+        return Ok(".".to_string());
+    }
+    Ok(_inner_join(rel_list[0], &rel_list[1..]))
+}
+
 fn _inner_split(path_str: &str) -> Result<(String, String), String> {
     let (mut head, tail) = match memchr::memrchr(MAIN_SEPARATOR as u8, path_str.as_bytes()) {
         Some(v) => path_str.split_at(v + 1),
@@ -374,6 +697,64 @@ fn _splitext<'a>(path_str: &'a str) -> Result<(&'a str, &'a str), String> {
     return Ok((path_str, ""));
 }
 
+/// Compound extensions recognized by `splitext_known` when no `known` list is given.
+const COMPOUND_EXTENSIONS: &[&str] = &[".tar.gz", ".tar.bz2", ".tar.xz", ".tar.zst", ".d.ts", ".min.js"];
+
+fn _splitext_all(path_str: &str) -> (&str, Vec<&str>) {
+    let name_index = match memchr::memrchr(SEP, path_str.as_bytes()) {
+        Some(v) => v + 1,
+        None => 0,
+    };
+    let name = &path_str[name_index..];
+    if name.ends_with('.') {
+        return (path_str, vec![]);
+    }
+    let lead = name.len() - name.trim_start_matches('.').len();
+    let ext_index = match memchr::memchr(b'.', &name.as_bytes()[lead..]) {
+        Some(v) => name_index + lead + v,
+        None => return (path_str, vec![]),
+    };
+    let (head, tail) = path_str.split_at(ext_index);
+    let mut suffixes = vec![];
+    let mut rest = tail;
+    while !rest.is_empty() {
+        let i = match memchr::memchr(b'.', &rest.as_bytes()[1..]) {
+            Some(v) => v + 1,
+            None => rest.len(),
+        };
+        let (suffix, tmp_rest) = rest.split_at(i);
+        suffixes.push(suffix);
+        rest = tmp_rest;
+    }
+    (head, suffixes)
+}
+
+fn _splitext_known<'a>(path_str: &'a str, known: &[String]) -> (&'a str, &'a str) {
+    let name_index = match memchr::memrchr(SEP, path_str.as_bytes()) {
+        Some(v) => v + 1,
+        None => 0,
+    };
+    let name = &path_str[name_index..];
+    let mut ext_len = 0;
+    for ext in known {
+        let ext = ext.trim_start_matches('.');
+        if ext.is_empty() || ext.len() < ext_len {
+            continue;
+        }
+        let stem = match name.strip_suffix(ext).and_then(|x| x.strip_suffix('.')) {
+            Some(v) => v,
+            None => continue,
+        };
+        if !stem.trim_start_matches('.').is_empty() {
+            ext_len = ext.len() + 1;
+        }
+    }
+    if ext_len == 0 {
+        return _splitext(path_str).unwrap();
+    }
+    path_str.split_at(path_str.len() - ext_len)
+}
+
 #[pymodule]
 #[pyo3(name = "_fpath")]
 fn init_mod(_py: Python, m: &PyModule) -> PyResult<()> {
@@ -421,30 +802,17 @@ fn init_mod(_py: Python, m: &PyModule) -> PyResult<()> {
     }
 
     #[pyfunction]
-    #[pyo3(name = "exists")]
-    pub fn exists(py: Python, path_str: &PyAny) -> PyResult<bool> {
+    #[pyo3(name = "exists", signature = (path_str, *, dir_fd=None, follow_symlinks=true))]
+    pub fn exists(py: Python, path_str: &PyAny, dir_fd: Option<i32>, follow_symlinks: bool) -> PyResult<bool> {
         let arg_str = pyobj2str(&py, path_str);
-        // TODO: from file descriptor
-        //let arg_str = match arg_str {
-        //    Err(e) => {
-        //        // for file descriptor argument
-        //        let gil = Python::acquire_gil();
-        //        let py = gil.python();
-        //        match path_str.extract::<i32>(py) {
-        //            Ok(fd) => {
-        //                let f = unsafe { fs::File::from_raw_fd(fd) };
-        //                Ok(f.exists())
-        //            }
-        //            Err(_) => Err(exceptions::TypeError::py_err(e)),
-        //        }
-        //    }
-        //    Ok(s) => Ok(s.0)
-        //};
         match arg_str {
             Err(e) => return Err(exceptions::PyTypeError::new_err(e)),
             _ => {}
         }
         let (arg_str, _) = arg_str.unwrap();
+        if dir_fd.is_some() || !follow_symlinks {
+            return Ok(at::file_type_at(dir_fd, OsStr::new(&arg_str), follow_symlinks).is_some());
+        }
         Ok(_exists(arg_str.as_str()))
     }
 
@@ -495,17 +863,41 @@ fn init_mod(_py: Python, m: &PyModule) -> PyResult<()> {
     }
 
     #[pyfunction]
-    #[pyo3(name = "islink")]
-    pub fn islink(py: Python, path_str: &PyAny) -> PyResult<bool> {
+    #[pyo3(name = "islink", signature = (path_str, *, dir_fd=None))]
+    pub fn islink(py: Python, path_str: &PyAny, dir_fd: Option<i32>) -> PyResult<bool> {
         let arg_str = pyobj2str(&py, path_str);
         match arg_str {
             Err(e) => return Err(exceptions::PyTypeError::new_err(e)),
             _ => {}
         }
         let (arg_str, _is_bytes) = arg_str.unwrap();
+        if dir_fd.is_some() {
+            return Ok(at::file_type_at(dir_fd, OsStr::new(&arg_str), false) == Some(libc::S_IFLNK));
+        }
         Ok(_islink(arg_str.as_str()))
     }
 
+    #[pyfunction]
+    #[pyo3(name = "lexists", signature = (path_str, *, dir_fd=None))]
+    pub fn lexists(py: Python, path_str: &PyAny, dir_fd: Option<i32>) -> PyResult<bool> {
+        let (arg_str, _) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        Ok(at::file_type_at(dir_fd, OsStr::new(&arg_str), false).is_some())
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "isfile", signature = (path_str, *, dir_fd=None, follow_symlinks=true))]
+    pub fn isfile(py: Python, path_str: &PyAny, dir_fd: Option<i32>, follow_symlinks: bool) -> PyResult<bool> {
+        let (arg_str, _) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        Ok(at::file_type_at(dir_fd, OsStr::new(&arg_str), follow_symlinks) == Some(libc::S_IFREG))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "isdir", signature = (path_str, *, dir_fd=None, follow_symlinks=true))]
+    pub fn isdir(py: Python, path_str: &PyAny, dir_fd: Option<i32>, follow_symlinks: bool) -> PyResult<bool> {
+        let (arg_str, _) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        Ok(at::file_type_at(dir_fd, OsStr::new(&arg_str), follow_symlinks) == Some(libc::S_IFDIR))
+    }
+
     #[pyfunction]
     #[pyo3(name = "join", text_signature = "(path_str, *args)")]
     pub fn join(py: Python, path_str: &PyAny, args: &PyTuple) -> PyResult<PyObject> {
@@ -541,9 +933,11 @@ fn init_mod(_py: Python, m: &PyModule) -> PyResult<()> {
         str2pyobj!(py, ret_str.as_str(), is_bytes)
     }
 
+    /// With `resolve`, both sides go through `realpath` first, so the
+    /// result stays correct across symlinked directories.
     #[pyfunction]
-    #[pyo3(name = "relpath")]
-    pub fn relpath(py: Python, path_str: &PyAny, start: &PyAny) -> PyResult<PyObject> {
+    #[pyo3(name = "relpath", signature = (path_str, start, *, resolve=false))]
+    pub fn relpath(py: Python, path_str: &PyAny, start: &PyAny, resolve: bool) -> PyResult<PyObject> {
         let arg_str = pyobj2str(&py, path_str);
         match arg_str {
             Err(e) => return Err(exceptions::PyTypeError::new_err(e)),
@@ -558,31 +952,201 @@ fn init_mod(_py: Python, m: &PyModule) -> PyResult<()> {
         }
         let (start_str, _) = start_str.unwrap();
 
-        str2pyobj!(py, _relpath(arg_str.as_str(), start_str.as_str()).as_str(), is_bytes)
+        let ret_str = if resolve {
+            _relpath(&_realpath(&arg_str, false, None, None)?, &_realpath(&start_str, false, None, None)?)
+        } else {
+            _relpath(arg_str.as_str(), start_str.as_str())
+        };
+        str2pyobj!(py, ret_str.as_str(), is_bytes)
+    }
+
+    /// Link text for `os.symlink(text, linkpath)` so that `linkpath` points
+    /// at `target`.
+    #[pyfunction]
+    #[pyo3(name = "symlink_target")]
+    pub fn symlink_target(py: Python, target: &PyAny, linkpath: &PyAny) -> PyResult<PyObject> {
+        let (target_str, is_bytes) = pyobj2str(&py, target).map_err(exceptions::PyTypeError::new_err)?;
+        let (link_str, link_is_bytes) = pyobj2str(&py, linkpath).map_err(exceptions::PyTypeError::new_err)?;
+        if is_bytes != link_is_bytes {
+            return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+        }
+        if target_str.is_empty() || link_str.is_empty() {
+            return Err(exceptions::PyValueError::new_err("no path specified"));
+        }
+        let ret = _symlink_target(&target_str, &link_str)?;
+        str2pyobj!(py, ret.as_str(), is_bytes)
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "relative_to", signature = (path_str, other, walk_up=false))]
+    pub fn relative_to(py: Python, path_str: &PyAny, other: &PyAny, walk_up: bool) -> PyResult<PyObject> {
+        let (arg_str, is_bytes) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        let (other_str, other_is_bytes) = pyobj2str(&py, other).map_err(exceptions::PyTypeError::new_err)?;
+        if is_bytes != other_is_bytes {
+            return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+        }
+
+        match _relative_to(arg_str.as_str(), other_str.as_str(), walk_up) {
+            Ok(s) => str2pyobj!(py, s.as_str(), is_bytes),
+            Err(e) => Err(exceptions::PyValueError::new_err(e)),
+        }
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "is_relative_to")]
+    pub fn is_relative_to(py: Python, path_str: &PyAny, other: &PyAny) -> PyResult<bool> {
+        let (arg_str, is_bytes) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        let (other_str, other_is_bytes) = pyobj2str(&py, other).map_err(exceptions::PyTypeError::new_err)?;
+        if is_bytes != other_is_bytes {
+            return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+        }
+
+        Ok(_is_relative_to(arg_str.as_str(), other_str.as_str()))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "realpath", signature = (path_str, *, strict=false, cache=None, root=None, dir_fd=None))]
+    pub fn realpath(py: Python, path_str: &PyAny, strict: bool, cache: Option<PyRef<RealpathCache>>, root: Option<&PyAny>, dir_fd: Option<i32>) -> PyResult<PyObject> {
+        let (arg_str, is_bytes) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        if let Some(fd) = dir_fd {
+            if root.is_some() {
+                return Err(exceptions::PyValueError::new_err("root can't be combined with dir_fd"));
+            }
+            if cache.is_some() {
+                return Err(exceptions::PyValueError::new_err("cache can't be combined with dir_fd"));
+            }
+            if !_isabs(&arg_str) {
+                let ret = _realpath_at(&arg_str, strict, fd)?;
+                return str2pyobj!(py, ret.as_str(), is_bytes);
+            }
+        }
+        let root = match root {
+            Some(r) => {
+                let (root, root_is_bytes) = pyobj2str(&py, r).map_err(exceptions::PyTypeError::new_err)?;
+                if root_is_bytes != is_bytes {
+                    return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+                }
+                Some(root)
+            }
+            None => None,
+        };
+        let ret = _realpath(arg_str.as_str(), strict, cache.as_ref().map(|c| &c.cache), root.as_deref())?;
+        str2pyobj!(py, ret.as_str(), is_bytes)
     }
 
+    /// `realpath(path)` with symlinks taken from the `links` mapping of
+    /// link path to link text instead of the filesystem. `existing`, if
+    /// given, lists the paths that exist besides the links and their
+    /// parents, so strict mode can report missing ones.
     #[pyfunction]
-    #[pyo3(name = "realpath", signature = (path_str, *_py_args, **py_kwargs))]
-    pub fn realpath(py: Python, path_str: &PyAny, _py_args: &PyTuple, py_kwargs: Option<&PyDict>) -> PyResult<PyObject> {
-        let strict = if py_kwargs.is_some() {
-            let kwargs = py_kwargs.expect("kwargs parse error");
-            match kwargs.get_item("strict").expect("kwargs parse error") {
-                Some(x) => x.extract::<bool>().expect("invalid strict value"),
-                None => false,
+    #[pyo3(name = "resolve_virtual", signature = (path, links, strict=false, *, existing=None, cwd=None))]
+    pub fn resolve_virtual(py: Python, path: &PyAny, links: &PyDict, strict: bool, existing: Option<&PyAny>, cwd: Option<&PyAny>) -> PyResult<PyObject> {
+        let (path_str, is_bytes) = pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?;
+        let arg = |obj: &PyAny| -> PyResult<String> {
+            let (s, b) = pyobj2str(&py, obj).map_err(exceptions::PyTypeError::new_err)?;
+            if b != is_bytes {
+                return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
             }
+            Ok(s)
+        };
+        let mut link_list = Vec::with_capacity(links.len());
+        for (k, v) in links.iter() {
+            link_list.push((arg(k)?, arg(v)?));
+        }
+        let existing = match existing {
+            Some(e) => Some(e.iter()?.map(|p| arg(p?)).collect::<PyResult<Vec<String>>>()?),
+            None => None,
+        };
+        let cwd = match cwd {
+            Some(c) => arg(c)?,
+            None => MAIN_SEPARATOR.to_string(),
+        };
+        let ret = _resolve_virtual(&path_str, link_list, existing, strict, &cwd)?;
+        str2pyobj!(py, ret.as_str(), is_bytes)
+    }
+
+    /// Every step `realpath(path)` takes, as dicts with `action`, `path`,
+    /// `target` (the raw link text of symlinks) and `resolved`.
+    #[pyfunction]
+    #[pyo3(name = "resolve_trace")]
+    pub fn resolve_trace(py: Python, path: &PyAny) -> PyResult<PyObject> {
+        let (path_str, is_bytes) = pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?;
+        let steps = _realpath_trace(&path_str)?;
+        let to_py = |s: &str| -> PyResult<PyObject> { str2pyobj!(py, s, is_bytes) };
+        let ret = PyList::empty(py);
+        for step in steps {
+            let d = PyDict::new(py);
+            d.set_item("action", step.action)?;
+            d.set_item("path", to_py(&step.path)?)?;
+            d.set_item("target", step.target.map(|t| to_py(&t)).transpose()?)?;
+            d.set_item("resolved", to_py(&step.resolved)?)?;
+            ret.append(d)?;
+        }
+        Ok(ret.to_object(py))
+    }
+
+    /// Join `unsafe_path` onto `root`, resolving `..` and symlinks one
+    /// component at a time without ever leaving `root`.
+    #[pyfunction]
+    #[pyo3(name = "secure_join")]
+    pub fn secure_join(py: Python, root: &PyAny, unsafe_path: &PyAny) -> PyResult<PyObject> {
+        let (root_str, is_bytes) = pyobj2str(&py, root).map_err(exceptions::PyTypeError::new_err)?;
+        let (path_str, path_is_bytes) = pyobj2str(&py, unsafe_path).map_err(exceptions::PyTypeError::new_err)?;
+        if is_bytes != path_is_bytes {
+            return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+        }
+        let (ret, ok) = _realpath_root(&path_str, &root_str, false, None)?;
+        if !ok {
+            let e = std::io::Error::from_raw_os_error(libc::ELOOP);
+            return Err(walkdir::oserror(&e, OsStr::new(&_inner_join(&root_str, &[&path_str]))));
+        }
+        str2pyobj!(py, ret.as_str(), is_bytes)
+    }
+
+    /// Whether `path` is `root` or below it, after resolving symlinks in
+    /// both unless `resolve` is false.
+    #[pyfunction]
+    #[pyo3(name = "is_within", signature = (root, path, resolve=true))]
+    pub fn is_within(py: Python, root: &PyAny, path: &PyAny, resolve: bool) -> PyResult<bool> {
+        let (root_str, is_bytes) = pyobj2str(&py, root).map_err(exceptions::PyTypeError::new_err)?;
+        let (path_str, path_is_bytes) = pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?;
+        if is_bytes != path_is_bytes {
+            return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+        }
+        let (root_str, path_str) = if resolve {
+            (_realpath(&root_str, false, None, None)?, _realpath(&path_str, false, None, None)?)
         } else {
-            false
+            (_abspath(&root_str)?, _abspath(&path_str)?)
         };
-        let arg_str = pyobj2str(&py, &path_str);
-        match arg_str {
-            Err(e) => return Err(exceptions::PyTypeError::new_err(e)),
-            _ => {}
+        Ok(_is_relative_to(&path_str, &root_str))
+    }
+
+    /// Open `path` beneath `root` through file descriptors, failing with
+    /// `EXDEV` instead of leaving it, and return the canonical path of
+    /// what was opened; with `return_fd`, also the descriptor, which the
+    /// caller then owns.
+    #[cfg(target_os = "linux")]
+    #[pyfunction]
+    #[pyo3(name = "resolve_beneath", signature = (root, path, *, return_fd=false, flags=None, use_openat2=true))]
+    pub fn resolve_beneath(py: Python, root: &PyAny, path: &PyAny, return_fd: bool, flags: Option<i32>, use_openat2: bool) -> PyResult<PyObject> {
+        let (root_str, is_bytes) = pyobj2str(&py, root).map_err(exceptions::PyTypeError::new_err)?;
+        let (path_str, path_is_bytes) = pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?;
+        if is_bytes != path_is_bytes {
+            return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
         }
-        let (arg_str, is_bytes) = arg_str.unwrap();
-        match _realpath(arg_str.as_str(), strict) {
-            Ok(s) => str2pyobj!(py, s.as_str(), is_bytes),
-            Err(e) => Err(e),
+        let flags = flags.unwrap_or(libc::O_PATH);
+        if flags & (libc::O_CREAT | libc::O_TMPFILE) != 0 {
+            return Err(exceptions::PyValueError::new_err("O_CREAT and O_TMPFILE are not supported"));
+        }
+        let (root_os, path_os) = (OsString::from(root_str), OsString::from(path_str));
+        let (ret, fd) = py
+            .allow_threads(|| beneath::resolve_beneath(&root_os, &path_os, flags, use_openat2))
+            .map_err(|e| walkdir::oserror(&e, &walkdir::join_name(&root_os, &path_os)))?;
+        let ret = walkdir::os2py(py, &ret, is_bytes);
+        if return_fd {
+            return Ok((ret, fd.into_raw()).to_object(py));
         }
+        Ok(ret)
     }
 
     #[pyfunction]
@@ -615,20 +1179,547 @@ fn init_mod(_py: Python, m: &PyModule) -> PyResult<()> {
         }
     }
 
+    #[pyfunction]
+    #[pyo3(name = "splitext_all")]
+    pub fn splitext_all(py: Python, path_str: &PyAny) -> PyResult<PyObject> {
+        let (arg_str, is_bytes) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        let (head, suffixes) = _splitext_all(arg_str.as_str());
+        let to_py = |s: &str| -> PyResult<PyObject> { str2pyobj!(py, s, is_bytes) };
+        let py_suffixes = suffixes.into_iter().map(to_py).collect::<PyResult<Vec<_>>>()?;
+        Ok(PyTuple::new(py, &[to_py(head)?, PyList::new(py, py_suffixes).to_object(py)]).to_object(py))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "splitext_known", signature = (path_str, known=None))]
+    pub fn splitext_known(py: Python, path_str: &PyAny, known: Option<&PyAny>) -> PyResult<PyObject> {
+        let (arg_str, is_bytes) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        let known_list: Vec<String> = match known {
+            Some(known) => {
+                let mut known_list = vec![];
+                for x in known.iter()? {
+                    let (ext, ext_is_bytes) = pyobj2str(&py, x?).map_err(exceptions::PyTypeError::new_err)?;
+                    if is_bytes != ext_is_bytes {
+                        return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+                    }
+                    known_list.push(ext);
+                }
+                known_list
+            }
+            None => COMPOUND_EXTENSIONS.iter().map(|x| x.to_string()).collect(),
+        };
+        let (head, tail) = _splitext_known(arg_str.as_str(), &known_list);
+        tuplestr2pyobj!(py, head, tail, is_bytes)
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "components")]
+    pub fn components(py: Python, path_str: &PyAny) -> PyResult<Components> {
+        let (arg_str, is_bytes) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        Ok(Components::new(arg_str.as_str(), is_bytes))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "ancestors")]
+    pub fn ancestors(py: Python, path_str: &PyAny) -> PyResult<Ancestors> {
+        let (arg_str, is_bytes) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        Ok(Ancestors::new(arg_str.as_str(), is_bytes))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "splitall")]
+    pub fn splitall(py: Python, path_str: &PyAny) -> PyResult<PyObject> {
+        let (arg_str, is_bytes) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        let (anchor, parts) = _normparts(arg_str.as_str());
+        let to_py = |s: &str| -> PyResult<PyObject> { str2pyobj!(py, s, is_bytes) };
+        let py_parts = parts.into_iter().map(to_py).collect::<PyResult<Vec<_>>>()?;
+        Ok(PyTuple::new(py, &[to_py(anchor)?, PyList::new(py, py_parts).to_object(py)]).to_object(py))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "joinall", signature = (parts, anchor=None))]
+    pub fn joinall(py: Python, parts: &PyAny, anchor: Option<&PyAny>) -> PyResult<PyObject> {
+        let (anchor_str, mut is_bytes) = match anchor {
+            Some(x) => {
+                let (x, x_is_bytes) = pyobj2str(&py, x).map_err(exceptions::PyTypeError::new_err)?;
+                (x, Some(x_is_bytes))
+            }
+            None => (String::new(), None),
+        };
+        let mut part_list: Vec<String> = vec![];
+        for x in parts.iter()? {
+            let (b, b_is_bytes) = pyobj2str(&py, x?).map_err(exceptions::PyTypeError::new_err)?;
+            if *is_bytes.get_or_insert(b_is_bytes) != b_is_bytes {
+                return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+            }
+            part_list.push(b);
+        }
+        let part_list: Vec<&str> = part_list.iter().map(|x| x.as_str()).collect();
+        let ret_str = _joinall(anchor_str.as_str(), &part_list);
+        str2pyobj!(py, ret_str.as_str(), is_bytes.unwrap_or(false))
+    }
+
+    #[pyfunction]
+    #[pyo3(signature = (path_str, pattern, case_sensitive=None))]
+    pub fn r#match(py: Python, path_str: &PyAny, pattern: &PyAny, case_sensitive: Option<bool>) -> PyResult<bool> {
+        let (arg_str, is_bytes) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        let (pattern_str, pattern_is_bytes) = pyobj2str(&py, pattern).map_err(exceptions::PyTypeError::new_err)?;
+        if is_bytes != pattern_is_bytes {
+            return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+        }
+        let compiled = pattern::compile_path_pattern(pattern_str.as_str(), case_sensitive.unwrap_or(true));
+        if compiled.is_empty() {
+            return Err(exceptions::PyValueError::new_err("empty pattern"));
+        }
+        Ok(compiled.match_right(arg_str.as_str()))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "full_match", signature = (path_str, pattern, case_sensitive=None))]
+    pub fn full_match(py: Python, path_str: &PyAny, pattern: &PyAny, case_sensitive: Option<bool>) -> PyResult<bool> {
+        let (arg_str, is_bytes) = pyobj2str(&py, path_str).map_err(exceptions::PyTypeError::new_err)?;
+        let (pattern_str, pattern_is_bytes) = pyobj2str(&py, pattern).map_err(exceptions::PyTypeError::new_err)?;
+        if is_bytes != pattern_is_bytes {
+            return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+        }
+        let compiled = pattern::compile_path_pattern(pattern_str.as_str(), case_sensitive.unwrap_or(true));
+        if compiled.is_empty() {
+            return Err(exceptions::PyValueError::new_err("empty pattern"));
+        }
+        Ok(compiled.full_match(arg_str.as_str()))
+    }
+
+    /// `os.walk` with the directories read in parallel. With `filter`,
+    /// `dirnames` and `filenames` only hold the entries that match, and
+    /// subdirectories are walked into by its `maxdepth`/`xdev` rules
+    /// whether they match or not. Removing a name from `dirnames` still
+    /// prunes it, but a subdirectory that doesn't match isn't listed and
+    /// can't be pruned; use `ignore` to keep the walk out of it.
+    #[pyfunction]
+    #[pyo3(name = "walk", signature = (top, topdown=true, onerror=None, followlinks=false, *, ignore=None, filter=None))]
+    pub fn walk(py: Python, top: &PyAny, topdown: bool, onerror: Option<PyObject>, followlinks: bool, ignore: Option<PyRef<IgnoreMatcher>>, filter: Option<PyRef<FindFilter>>) -> PyResult<Walk> {
+        let (top_str, is_bytes) = pyobj2str(&py, top).map_err(exceptions::PyTypeError::new_err)?;
+        let ignore = ignore.map(|x| x.ignore.clone());
+        let filter = filter.map(|x| x.filter.clone());
+        Ok(Walk::new(OsString::from(top_str), topdown, onerror, followlinks, ignore, filter, is_bytes))
+    }
+
+    fn read_dir_typed(py: Python, path: Option<&PyAny>) -> PyResult<(OsString, Vec<at::TypedEntry>, bool)> {
+        let (path_str, is_bytes) = match path {
+            Some(p) => pyobj2str(&py, p).map_err(exceptions::PyTypeError::new_err)?,
+            None => (".".to_string(), false),
+        };
+        let path = OsString::from(path_str);
+        let entries = py.allow_threads(|| at::read_dir_typed(&path)).map_err(|e| walkdir::oserror(&e, &path))?;
+        Ok((path, entries, is_bytes))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "listdir_typed", signature = (path=None))]
+    pub fn listdir_typed(py: Python, path: Option<&PyAny>) -> PyResult<PyObject> {
+        let (_, entries, is_bytes) = read_dir_typed(py, path)?;
+        let ret = entries.iter().map(|(name, entry_type, _)| (walkdir::os2py(py, name, is_bytes), entry_type.as_str()).to_object(py));
+        Ok(PyList::new(py, ret.collect::<Vec<_>>()).to_object(py))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "scandir", signature = (path=None, *, filter=None))]
+    pub fn scandir(py: Python, path: Option<&PyAny>, filter: Option<PyRef<FindFilter>>) -> PyResult<Vec<DirEntry>> {
+        let (path, entries, is_bytes) = read_dir_typed(py, path)?;
+        let entries = match filter {
+            Some(f) => {
+                let filter = f.filter.clone();
+                py.allow_threads(|| filter.select_entries(&path, entries)).map_err(|e| walkdir::oserror(&e, &path))?
+            }
+            None => entries,
+        };
+        Ok(entries
+            .into_iter()
+            .map(|(name, entry_type, ino)| {
+                let full = walkdir::join_name(&path, &name);
+                DirEntry::new(name, full, entry_type, ino, is_bytes)
+            })
+            .collect())
+    }
+
+    /// `onerror` is called with an `OSError` for each directory or entry
+    /// that couldn't be read, once the scan is done; their sizes are
+    /// missing from the totals.
+    #[pyfunction]
+    #[pyo3(name = "disk_usage_tree", signature = (root, apparent=false, follow_symlinks=false, xdev=true, max_depth=None, onerror=None))]
+    pub fn disk_usage_tree(py: Python, root: &PyAny, apparent: bool, follow_symlinks: bool, xdev: bool, max_depth: Option<usize>, onerror: Option<PyObject>) -> PyResult<PyObject> {
+        let (root_str, is_bytes) = pyobj2str(&py, root).map_err(exceptions::PyTypeError::new_err)?;
+        let root = OsString::from(root_str);
+        let opts = du::DuOptions { apparent, follow_symlinks, xdev };
+        let (sizes, errors) = py.allow_threads(|| du::disk_usage_tree(&root, &opts, max_depth)).map_err(|e| walkdir::oserror(&e, &root))?;
+        if let Some(onerror) = onerror {
+            for (e, path) in errors.iter() {
+                onerror.call1(py, (walkdir::oserror(e, path).value(py),))?;
+            }
+        }
+        let ret = PyDict::new(py);
+        for (path, size) in sizes {
+            ret.set_item(walkdir::os2py(py, &path, is_bytes), size)?;
+        }
+        Ok(ret.to_object(py))
+    }
+
+    fn which_args(py: Python, cmd: &PyAny, path: Option<&PyAny>) -> PyResult<(String, String, bool)> {
+        let (cmd_str, is_bytes) = pyobj2str(&py, cmd).map_err(exceptions::PyTypeError::new_err)?;
+        let path_str = match path {
+            Some(p) => {
+                let (p, p_is_bytes) = pyobj2str(&py, p).map_err(exceptions::PyTypeError::new_err)?;
+                if p_is_bytes != is_bytes {
+                    return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+                }
+                p
+            }
+            None => env::var("PATH").unwrap_or_else(|_| pathsearch::default_path()),
+        };
+        Ok((cmd_str, path_str, is_bytes))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "which", signature = (cmd, mode=libc::F_OK | libc::X_OK, path=None))]
+    pub fn which(py: Python, cmd: &PyAny, mode: i32, path: Option<&PyAny>) -> PyResult<Option<PyObject>> {
+        let (cmd_str, path_str, is_bytes) = which_args(py, cmd, path)?;
+        if !_dirname(&cmd_str).is_empty() {
+            let found = py.allow_threads(|| pathsearch::access_check(&cmd_str, mode));
+            return found.then(|| str2pyobj!(py, &cmd_str, is_bytes)).transpose();
+        }
+        let found = py.allow_threads(|| pathsearch::which_all(&cmd_str, mode, &path_str, true));
+        found.first().map(|x| str2pyobj!(py, x, is_bytes)).transpose()
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "which_all", signature = (cmd, mode=libc::F_OK | libc::X_OK, path=None))]
+    pub fn which_all(py: Python, cmd: &PyAny, mode: i32, path: Option<&PyAny>) -> PyResult<PyObject> {
+        let (cmd_str, path_str, is_bytes) = which_args(py, cmd, path)?;
+        let found = if !_dirname(&cmd_str).is_empty() {
+            let found = py.allow_threads(|| pathsearch::access_check(&cmd_str, mode));
+            if found { vec![cmd_str] } else { vec![] }
+        } else {
+            py.allow_threads(|| pathsearch::which_all(&cmd_str, mode, &path_str, false))
+        };
+        let found = found.iter().map(|x| str2pyobj!(py, x, is_bytes)).collect::<PyResult<Vec<PyObject>>>()?;
+        Ok(PyList::new(py, found).to_object(py))
+    }
+
+    /// A search path and extra entries, which must all be str or all bytes.
+    fn pathlist_args(py: Python, path: &PyAny, entries: &PyTuple) -> PyResult<(String, Vec<String>, bool)> {
+        let (path_str, is_bytes) = pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?;
+        let mut ret = vec![];
+        for e in entries.iter() {
+            let (e, e_is_bytes) = pyobj2str(&py, e).map_err(exceptions::PyTypeError::new_err)?;
+            if e_is_bytes != is_bytes {
+                return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+            }
+            ret.push(e);
+        }
+        Ok((path_str, ret, is_bytes))
+    }
+
+    fn pathlist_obj(py: Python, entries: Vec<String>, is_bytes: bool) -> PyResult<PyObject> {
+        let joined = pathsearch::join_pathlist(&entries).map_err(exceptions::PyValueError::new_err)?;
+        str2pyobj!(py, &joined, is_bytes)
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "pathlist_split")]
+    pub fn pathlist_split(py: Python, path: &PyAny) -> PyResult<PyObject> {
+        let (path_str, is_bytes) = pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?;
+        let entries = pathsearch::split_pathlist(&path_str);
+        let entries = entries.iter().map(|x| str2pyobj!(py, x, is_bytes)).collect::<PyResult<Vec<PyObject>>>()?;
+        Ok(PyList::new(py, entries).to_object(py))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "pathlist_join")]
+    pub fn pathlist_join(py: Python, entries: &PyAny) -> PyResult<PyObject> {
+        let mut ret = vec![];
+        let mut is_bytes = None;
+        for e in entries.iter()? {
+            let (e, e_is_bytes) = pyobj2str(&py, e?).map_err(exceptions::PyTypeError::new_err)?;
+            if *is_bytes.get_or_insert(e_is_bytes) != e_is_bytes {
+                return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+            }
+            ret.push(e);
+        }
+        pathlist_obj(py, ret, is_bytes.unwrap_or(false))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "pathlist_prepend", signature = (path, *entries))]
+    pub fn pathlist_prepend(py: Python, path: &PyAny, entries: &PyTuple) -> PyResult<PyObject> {
+        let (path_str, entries, is_bytes) = pathlist_args(py, path, entries)?;
+        let all = entries.iter().map(|x| x.as_str()).chain(pathsearch::split_pathlist(&path_str));
+        pathlist_obj(py, pathsearch::normalize_pathlist(all), is_bytes)
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "pathlist_append", signature = (path, *entries))]
+    pub fn pathlist_append(py: Python, path: &PyAny, entries: &PyTuple) -> PyResult<PyObject> {
+        let (path_str, entries, is_bytes) = pathlist_args(py, path, entries)?;
+        let all = pathsearch::split_pathlist(&path_str).into_iter().chain(entries.iter().map(|x| x.as_str()));
+        pathlist_obj(py, pathsearch::normalize_pathlist(all), is_bytes)
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "pathlist_remove", signature = (path, *entries))]
+    pub fn pathlist_remove(py: Python, path: &PyAny, entries: &PyTuple) -> PyResult<PyObject> {
+        let (path_str, entries, is_bytes) = pathlist_args(py, path, entries)?;
+        let removed: Vec<String> = entries.iter().map(|x| _normpath(x)).collect();
+        let mut ret = pathsearch::normalize_pathlist(pathsearch::split_pathlist(&path_str));
+        ret.retain(|x| !removed.contains(x));
+        pathlist_obj(py, ret, is_bytes)
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "pathlist_normalize")]
+    pub fn pathlist_normalize(py: Python, path: &PyAny) -> PyResult<PyObject> {
+        let (path_str, is_bytes) = pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?;
+        pathlist_obj(py, pathsearch::normalize_pathlist(pathsearch::split_pathlist(&path_str)), is_bytes)
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "pathlist_expand")]
+    pub fn pathlist_expand(py: Python, path: &PyAny) -> PyResult<PyObject> {
+        let (path_str, is_bytes) = pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?;
+        // a variable may hold a whole list, like `$HOME/bin:$PATH`
+        let expanded: Vec<String> = pathsearch::split_pathlist(&path_str).into_iter().map(pathsearch::expand_entry).collect();
+        let entries = expanded
+            .iter()
+            .flat_map(|x| if x.is_empty() { vec![""] } else { pathsearch::split_pathlist(x) })
+            .map(|x| x.to_string())
+            .collect();
+        pathlist_obj(py, entries, is_bytes)
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "pathlist_find")]
+    pub fn pathlist_find(py: Python, path: &PyAny, name: &PyAny) -> PyResult<Option<PyObject>> {
+        let (path_str, names, is_bytes) = pathlist_args(py, path, PyTuple::new(py, [name]))?;
+        let name = names[0].as_str();
+        let found = py.allow_threads(|| pathsearch::split_pathlist(&path_str).into_iter().map(|d| _inner_join(d, &[name])).find(|x| _exists(x)));
+        found.map(|x| str2pyobj!(py, &x, is_bytes)).transpose()
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "which_cache_clear")]
+    pub fn which_cache_clear() {
+        pathsearch::clear_cache()
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "find", signature = (top, *, r#type=None, name=None, size=None, newer=None, mtime=None, user=None, group=None, mindepth=0, maxdepth=None, xdev=false, followlinks=false, onerror=None))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn find(
+        py: Python,
+        top: &PyAny,
+        r#type: Option<&str>,
+        name: Option<&str>,
+        size: Option<&PyAny>,
+        newer: Option<&PyAny>,
+        mtime: Option<&PyAny>,
+        user: Option<&PyAny>,
+        group: Option<&PyAny>,
+        mindepth: usize,
+        maxdepth: Option<usize>,
+        xdev: bool,
+        followlinks: bool,
+        onerror: Option<PyObject>,
+    ) -> PyResult<Find> {
+        let (top_str, is_bytes) = pyobj2str(&py, top).map_err(exceptions::PyTypeError::new_err)?;
+        let filter = FindFilter::new(py, r#type, name, size, newer, mtime, user, group, mindepth, maxdepth, xdev)?;
+        Ok(Find::new(OsString::from(top_str), filter.filter, followlinks, onerror, is_bytes))
+    }
+
+    fn glob_args(py: Python, pathname: &PyAny, root_dir: Option<&PyAny>) -> PyResult<(String, OsString, bool)> {
+        let (pathname_str, is_bytes) = pyobj2str(&py, pathname).map_err(exceptions::PyTypeError::new_err)?;
+        let root_dir = match root_dir {
+            Some(x) => {
+                let (root_dir, root_is_bytes) = pyobj2str(&py, x).map_err(exceptions::PyTypeError::new_err)?;
+                if root_is_bytes != is_bytes {
+                    return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+                }
+                OsString::from(root_dir)
+            }
+            None => OsString::new(),
+        };
+        Ok((pathname_str, root_dir, is_bytes))
+    }
+
+    fn glob_options(dir_fd: Option<i32>, recursive: bool, include_hidden: bool, ignore: Option<PyRef<IgnoreMatcher>>) -> PyResult<GlobOptions> {
+        if dir_fd.is_some() && ignore.is_some() {
+            return Err(exceptions::PyValueError::new_err("ignore can't be combined with dir_fd"));
+        }
+        let ignore = ignore.map(|x| x.ignore.clone());
+        Ok(GlobOptions { dir_fd, recursive, include_hidden, ignore })
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "glob", signature = (pathname, *, root_dir=None, dir_fd=None, recursive=false, include_hidden=false, ignore=None))]
+    pub fn glob(py: Python, pathname: &PyAny, root_dir: Option<&PyAny>, dir_fd: Option<i32>, recursive: bool, include_hidden: bool, ignore: Option<PyRef<IgnoreMatcher>>) -> PyResult<PyObject> {
+        let (pathname_str, root_dir, is_bytes) = glob_args(py, pathname, root_dir)?;
+        let opts = glob_options(dir_fd, recursive, include_hidden, ignore)?;
+        let ret: Vec<OsString> = py.allow_threads(move || globbing::iglob(opts, pathname_str, root_dir).collect());
+        Ok(PyList::new(py, ret.iter().map(|x| walkdir::os2py(py, x, is_bytes))).to_object(py))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "iglob", signature = (pathname, *, root_dir=None, dir_fd=None, recursive=false, include_hidden=false, ignore=None))]
+    pub fn iglob(py: Python, pathname: &PyAny, root_dir: Option<&PyAny>, dir_fd: Option<i32>, recursive: bool, include_hidden: bool, ignore: Option<PyRef<IgnoreMatcher>>) -> PyResult<Glob> {
+        let (pathname_str, root_dir, is_bytes) = glob_args(py, pathname, root_dir)?;
+        let opts = glob_options(dir_fd, recursive, include_hidden, ignore)?;
+        Ok(Glob::new(globbing::iglob(opts, pathname_str, root_dir), is_bytes))
+    }
+
+    /// fnmatch arguments: str as is, bytes decoded as latin-1 like CPython's
+    /// `_compile_pattern`, and os.PathLike only where `normcase` would accept it.
+    fn fnmatch_arg(obj: &PyAny, fspath: bool) -> PyResult<(String, bool)> {
+        if let Ok(s) = obj.downcast::<PyString>() {
+            return Ok((s.to_str()?.to_string(), false));
+        }
+        if let Ok(b) = obj.downcast::<PyBytes>() {
+            return Ok((b.as_bytes().iter().map(|&x| x as char).collect(), true));
+        }
+        if fspath {
+            if let Ok(func) = obj.getattr("__fspath__") {
+                return fnmatch_arg(func.call0()?, false);
+            }
+        }
+        Err(exceptions::PyTypeError::new_err(format!("expected string or bytes-like object, got '{}'", obj.get_type().name()?)))
+    }
+
+    fn fnmatch_check(name_is_bytes: bool, pat_is_bytes: bool) -> PyResult<()> {
+        match (name_is_bytes, pat_is_bytes) {
+            (false, true) => Err(exceptions::PyTypeError::new_err("cannot use a bytes pattern on a string-like object")),
+            (true, false) => Err(exceptions::PyTypeError::new_err("cannot use a string pattern on a bytes-like object")),
+            _ => Ok(()),
+        }
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "fnmatch")]
+    pub fn fnmatch(name: &PyAny, pat: &PyAny) -> PyResult<bool> {
+        let (name_str, name_is_bytes) = fnmatch_arg(name, true)?;
+        let (pat_str, pat_is_bytes) = fnmatch_arg(pat, true)?;
+        fnmatch_check(name_is_bytes, pat_is_bytes)?;
+        Ok(pattern::compile_fnmatch(pat_str.as_str()).is_match(name_str.as_str()))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "fnmatchcase")]
+    pub fn fnmatchcase(name: &PyAny, pat: &PyAny) -> PyResult<bool> {
+        let (name_str, name_is_bytes) = fnmatch_arg(name, false)?;
+        let (pat_str, pat_is_bytes) = fnmatch_arg(pat, false)?;
+        fnmatch_check(name_is_bytes, pat_is_bytes)?;
+        Ok(pattern::compile_fnmatch(pat_str.as_str()).is_match(name_str.as_str()))
+    }
+
+    #[pyfunction]
+    #[pyo3(name = "fnmatch_filter")]
+    pub fn fnmatch_filter(py: Python, names: &PyAny, pat: &PyAny) -> PyResult<PyObject> {
+        let (pat_str, pat_is_bytes) = fnmatch_arg(pat, true)?;
+        let mut objs = vec![];
+        let mut name_list = vec![];
+        for name in names.iter()? {
+            let name = name?;
+            let (name_str, name_is_bytes) = fnmatch_arg(name, false)?;
+            fnmatch_check(name_is_bytes, pat_is_bytes)?;
+            objs.push(name);
+            name_list.push(name_str);
+        }
+        let matched: Vec<bool> = py.allow_threads(move || {
+            let compiled = pattern::compile_fnmatch(pat_str.as_str());
+            name_list.iter().map(|x| compiled.is_match(x)).collect()
+        });
+        let ret: Vec<&PyAny> = objs.into_iter().zip(matched).filter(|(_, ok)| *ok).map(|(x, _)| x).collect();
+        Ok(PyList::new(py, ret).to_object(py))
+    }
+
+    /// `fnmatch.translate` for the running interpreter. Before 3.11, `re`
+    /// has no atomic groups and the stand-in groups are numbered from the
+    /// stdlib's own counter, so results can still be joined with `|`.
+    #[pyfunction]
+    #[pyo3(name = "fnmatch_translate")]
+    pub fn fnmatch_translate(py: Python, pat: &str) -> PyResult<String> {
+        if py.version_info() >= (3, 11) {
+            return pattern::translate(pat, None);
+        }
+        let counter = py.import("fnmatch")?.getattr("_nextgroupnum")?;
+        let mut next_group = || counter.call0()?.extract::<usize>();
+        pattern::translate(pat, Some(&mut next_group))
+    }
+
+    m.add("COMPOUND_EXTENSIONS", PyTuple::new(_py, COMPOUND_EXTENSIONS))?;
+
+    m.add_class::<Components>()?;
+    m.add_class::<Ancestors>()?;
+    m.add_class::<Walk>()?;
+    m.add_class::<Glob>()?;
+    m.add_class::<GlobSet>()?;
+    m.add_class::<IgnoreMatcher>()?;
+    m.add_class::<Find>()?;
+    m.add_class::<FindFilter>()?;
+    m.add_class::<DirEntry>()?;
+    m.add_class::<RealpathCache>()?;
+
     m.add_function(wrap_pyfunction!(abspath, m)?)?;
+    m.add_function(wrap_pyfunction!(ancestors, m)?)?;
     m.add_function(wrap_pyfunction!(basename, m)?)?;
+    m.add_function(wrap_pyfunction!(components, m)?)?;
     m.add_function(wrap_pyfunction!(dirname, m)?)?;
+    m.add_function(wrap_pyfunction!(disk_usage_tree, m)?)?;
     m.add_function(wrap_pyfunction!(exists, m)?)?;
     m.add_function(wrap_pyfunction!(expanduser, m)?)?;
     m.add_function(wrap_pyfunction!(expandvars, m)?)?;
+    m.add_function(wrap_pyfunction!(find, m)?)?;
+    m.add_function(wrap_pyfunction!(fnmatch, m)?)?;
+    m.add_function(wrap_pyfunction!(fnmatchcase, m)?)?;
+    m.add_function(wrap_pyfunction!(fnmatch_filter, m)?)?;
+    m.add_function(wrap_pyfunction!(fnmatch_translate, m)?)?;
+    m.add_function(wrap_pyfunction!(full_match, m)?)?;
+    m.add_function(wrap_pyfunction!(glob, m)?)?;
+    m.add_function(wrap_pyfunction!(iglob, m)?)?;
     m.add_function(wrap_pyfunction!(isabs, m)?)?;
+    m.add_function(wrap_pyfunction!(isdir, m)?)?;
+    m.add_function(wrap_pyfunction!(isfile, m)?)?;
     m.add_function(wrap_pyfunction!(islink, m)?)?;
     m.add_function(wrap_pyfunction!(join, m)?)?;
+    m.add_function(wrap_pyfunction!(joinall, m)?)?;
+    m.add_function(wrap_pyfunction!(lexists, m)?)?;
+    m.add_function(wrap_pyfunction!(r#match, m)?)?;
+    m.add_function(wrap_pyfunction!(listdir_typed, m)?)?;
     m.add_function(wrap_pyfunction!(normpath, m)?)?;
+    m.add_function(wrap_pyfunction!(pathlist_append, m)?)?;
+    m.add_function(wrap_pyfunction!(pathlist_expand, m)?)?;
+    m.add_function(wrap_pyfunction!(pathlist_find, m)?)?;
+    m.add_function(wrap_pyfunction!(pathlist_join, m)?)?;
+    m.add_function(wrap_pyfunction!(pathlist_normalize, m)?)?;
+    m.add_function(wrap_pyfunction!(pathlist_prepend, m)?)?;
+    m.add_function(wrap_pyfunction!(pathlist_remove, m)?)?;
+    m.add_function(wrap_pyfunction!(pathlist_split, m)?)?;
     m.add_function(wrap_pyfunction!(relpath, m)?)?;
+    m.add_function(wrap_pyfunction!(symlink_target, m)?)?;
     m.add_function(wrap_pyfunction!(realpath, m)?)?;
+    m.add_function(wrap_pyfunction!(relative_to, m)?)?;
+    m.add_function(wrap_pyfunction!(resolve_trace, m)?)?;
+    m.add_function(wrap_pyfunction!(resolve_virtual, m)?)?;
+    #[cfg(target_os = "linux")]
+    m.add_function(wrap_pyfunction!(resolve_beneath, m)?)?;
+    m.add_function(wrap_pyfunction!(is_relative_to, m)?)?;
+    m.add_function(wrap_pyfunction!(is_within, m)?)?;
+    m.add_function(wrap_pyfunction!(scandir, m)?)?;
+    m.add_function(wrap_pyfunction!(secure_join, m)?)?;
     m.add_function(wrap_pyfunction!(split, m)?)?;
+    m.add_function(wrap_pyfunction!(splitall, m)?)?;
     m.add_function(wrap_pyfunction!(splitext, m)?)?;
+    m.add_function(wrap_pyfunction!(splitext_all, m)?)?;
+    m.add_function(wrap_pyfunction!(splitext_known, m)?)?;
+    m.add_function(wrap_pyfunction!(walk, m)?)?;
+    m.add_function(wrap_pyfunction!(which, m)?)?;
+    m.add_function(wrap_pyfunction!(which_all, m)?)?;
+    m.add_function(wrap_pyfunction!(which_cache_clear, m)?)?;
 
     Ok(())
 }
@@ -636,8 +1727,9 @@ fn init_mod(_py: Python, m: &PyModule) -> PyResult<()> {
 #[cfg(test)]
 mod tests {
     use std::env::current_dir;
-    use std::collections::HashMap;
-    use super::{_abspath, _dirname, _joinrealpath, _realpath};
+    use super::{_abspath, _dirname, _joinrealpath, _pop_name, _realpath, _relative_to, _is_relative_to,
+                _splitext_all, _splitext_known, _normparts, _normpath, _joinall,
+                _split_parts, _resolve_virtual, LinkSource};
 
     #[test]
     fn abspath() {
@@ -679,14 +1771,96 @@ mod tests {
     #[test]
     fn realpath() {
         let fname = "//";
-        let result_str = _realpath(fname, false).unwrap();
+        let result_str = _realpath(fname, false, None, None).unwrap();
         assert_eq!(result_str, "/");
     }
 
     #[test]
     fn test_joinrealpath() {
         let fname = "//";
-        let ret = _joinrealpath("", fname, false, &HashMap::new()).expect("joinrealpath error");
+        let ret = _joinrealpath("", fname, false, LinkSource::Fs(None), "", None).expect("joinrealpath error");
         assert_eq!(ret, ("/".to_string(), true));
     }
+
+    #[test]
+    fn resolve_virtual() {
+        let links = vec![("l".to_string(), "../b".to_string()), ("/loop".to_string(), "loop".to_string())];
+        assert_eq!(_resolve_virtual("l/c", links.clone(), None, false, "/a").unwrap(), "/b/c");
+        assert_eq!(_resolve_virtual("/loop/x", links.clone(), None, false, "/").unwrap(), "/loop/x");
+        assert!(_resolve_virtual("/loop", links.clone(), None, true, "/").is_err());
+        assert!(_resolve_virtual("/b/c", links, Some(vec![]), true, "/").is_err());
+    }
+
+    #[test]
+    fn pop_name() {
+        for (path, ok) in [("/a/b", "/a"), ("/a", "/"), ("/", "/"), ("a", ""), ("", ".."), ("..", "../.."), ("a//b", "a")] {
+            let mut path = path.to_string();
+            _pop_name(&mut path);
+            assert_eq!(path, ok);
+        }
+    }
+
+    #[test]
+    fn relative_to() {
+        assert_eq!(_relative_to("/a/b/c", "/a", false).unwrap(), "b/c");
+        assert_eq!(_relative_to("/a/b", "/a/b/", false).unwrap(), ".");
+        assert_eq!(_relative_to("a//./b", "a", false).unwrap(), "b");
+        assert!(_relative_to("/a/b", "/c", false).is_err());
+        assert!(_relative_to("/a/b", "a", false).is_err());
+
+        assert_eq!(_relative_to("/a/b", "/c/d", true).unwrap(), "../../a/b");
+        assert_eq!(_relative_to("a", "a/b", true).unwrap(), "..");
+        assert!(_relative_to("/a/b", "/a/../c", true).is_err());
+        assert!(_relative_to("/a/b", "a", true).is_err());
+
+        assert!(_is_relative_to("/a/b", "/a"));
+        assert!(_is_relative_to("//a/b", "//a"));
+        assert!(!_is_relative_to("//a/b", "/a"));
+        assert!(!_is_relative_to("/ab", "/a"));
+    }
+
+    #[test]
+    fn splitext_all() {
+        assert_eq!(_splitext_all("dist/archive.tar.gz"), ("dist/archive", vec![".tar", ".gz"]));
+        assert_eq!(_splitext_all("/path/to/file"), ("/path/to/file", vec![]));
+        assert_eq!(_splitext_all(".bashrc"), (".bashrc", vec![]));
+        assert_eq!(_splitext_all("..a.b"), ("..a", vec![".b"]));
+        assert_eq!(_splitext_all("a..b"), ("a", vec![".", ".b"]));
+        assert_eq!(_splitext_all("a.b/c"), ("a.b/c", vec![]));
+        assert_eq!(_splitext_all("file."), ("file.", vec![]));
+    }
+
+    #[test]
+    fn splitext_known() {
+        let known: Vec<String> = vec![".tar.gz".to_string(), "d.ts".to_string(), ".gz".to_string()];
+        assert_eq!(_splitext_known("dist/archive.tar.gz", &known), ("dist/archive", ".tar.gz"));
+        assert_eq!(_splitext_known("types/index.d.ts", &known), ("types/index", ".d.ts"));
+        assert_eq!(_splitext_known("foo.gz", &known), ("foo", ".gz"));
+        assert_eq!(_splitext_known(".tar.gz", &known), (".tar", ".gz"));
+        assert_eq!(_splitext_known("main.min.js", &known), ("main.min", ".js"));
+        assert_eq!(_splitext_known("atar.gz", &known), ("atar", ".gz"));
+    }
+
+    #[test]
+    fn splitall() {
+        assert_eq!(_normparts("/path//to/../file.txt"), ("/", vec!["path", "file.txt"]));
+        assert_eq!(_normparts("//path/to"), ("//", vec!["path", "to"]));
+        assert_eq!(_normparts("///path/to"), ("/", vec!["path", "to"]));
+        assert_eq!(_normparts("../path/./to/"), ("", vec!["..", "path", "to"]));
+        assert_eq!(_normparts(""), ("", vec![]));
+
+        for p in ["/path//to/../file.txt", "//path/to", "../a/./b/", "", ".", "/", "//"] {
+            let (anchor, parts) = _normparts(p);
+            assert_eq!(_joinall(anchor, &parts), _normpath(p));
+        }
+    }
+
+    #[test]
+    fn split_parts() {
+        assert_eq!(_split_parts("/path//to/./file.txt"), ("/", vec!["path", "to", "file.txt"]));
+        assert_eq!(_split_parts("//path/"), ("//", vec!["path"]));
+        assert_eq!(_split_parts("///path"), ("/", vec!["path"]));
+        assert_eq!(_split_parts("a/../b"), ("", vec!["a", "..", "b"]));
+        assert_eq!(_split_parts(""), ("", vec![]));
+    }
 }
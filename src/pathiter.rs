@@ -0,0 +1,152 @@
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+
+use crate::{_normpath, SEP};
+
+#[inline(always)]
+fn anchor_len(buf: &str) -> usize {
+    buf.len() - buf.trim_start_matches(SEP as char).len()
+}
+
+/// Iterator over the components of a normalized path. The anchor ("/" or "//")
+/// comes first for absolute paths, "." has no components.
+#[pyclass(module = "fpath._fpath")]
+pub struct Components {
+    buf: String,
+    pos: usize,
+    anchor_len: usize,
+    is_bytes: bool,
+}
+
+impl Components {
+    pub fn new(path_str: &str, is_bytes: bool) -> Self {
+        let buf = _normpath(path_str);
+        let anchor_len = anchor_len(buf.as_str());
+        let pos = if buf == "." { buf.len() } else { 0 };
+        Components { buf, pos, anchor_len, is_bytes }
+    }
+
+    fn next_component(&mut self) -> Option<&str> {
+        if self.pos < self.anchor_len {
+            self.pos = self.anchor_len;
+            return Some(&self.buf[..self.anchor_len]);
+        }
+        if self.pos >= self.buf.len() {
+            return None;
+        }
+        let start = self.pos;
+        let end = match memchr::memchr(SEP, &self.buf.as_bytes()[start..]) {
+            Some(i) => start + i,
+            None => self.buf.len(),
+        };
+        self.pos = end + 1;
+        Some(&self.buf[start..end])
+    }
+}
+
+#[pymethods]
+impl Components {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
+        let py = slf.py();
+        let is_bytes = slf.is_bytes;
+        slf.next_component().map(|x| str2pyobj!(py, x, is_bytes)).transpose()
+    }
+}
+
+/// Iterator over the logical parents of a normalized path, ending at the
+/// anchor for absolute paths and at "." for relative ones.
+#[pyclass(module = "fpath._fpath")]
+pub struct Ancestors {
+    buf: String,
+    end: usize,
+    anchor_len: usize,
+    is_bytes: bool,
+}
+
+impl Ancestors {
+    pub fn new(path_str: &str, is_bytes: bool) -> Self {
+        let buf = _normpath(path_str);
+        let anchor_len = anchor_len(buf.as_str());
+        let end = if buf == "." { 0 } else { buf.len() };
+        Ancestors { buf, end, anchor_len, is_bytes }
+    }
+
+    fn next_ancestor(&mut self) -> Option<&str> {
+        if self.end <= self.anchor_len {
+            return None;
+        }
+        match memchr::memrchr(SEP, &self.buf.as_bytes()[self.anchor_len..self.end]) {
+            Some(i) => {
+                self.end = self.anchor_len + i;
+                Some(&self.buf[..self.end])
+            }
+            None => {
+                self.end = self.anchor_len;
+                if self.anchor_len > 0 {
+                    Some(&self.buf[..self.anchor_len])
+                } else {
+                    Some(".")
+                }
+            }
+        }
+    }
+}
+
+#[pymethods]
+impl Ancestors {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
+        let py = slf.py();
+        let is_bytes = slf.is_bytes;
+        slf.next_ancestor().map(|x| str2pyobj!(py, x, is_bytes)).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ancestors, Components};
+
+    fn components(path_str: &str) -> Vec<String> {
+        let mut it = Components::new(path_str, false);
+        let mut ret = vec![];
+        while let Some(x) = it.next_component() {
+            ret.push(x.to_string());
+        }
+        ret
+    }
+
+    fn ancestors(path_str: &str) -> Vec<String> {
+        let mut it = Ancestors::new(path_str, false);
+        let mut ret = vec![];
+        while let Some(x) = it.next_ancestor() {
+            ret.push(x.to_string());
+        }
+        ret
+    }
+
+    #[test]
+    fn test_components() {
+        assert_eq!(components("/path//to/./file.txt"), vec!["/", "path", "to", "file.txt"]);
+        assert_eq!(components("//path/to/"), vec!["//", "path", "to"]);
+        assert_eq!(components("path/../to"), vec!["to"]);
+        assert_eq!(components("/"), vec!["/"]);
+        assert!(components("").is_empty());
+        assert!(components(".").is_empty());
+    }
+
+    #[test]
+    fn test_ancestors() {
+        assert_eq!(ancestors("/path/to/file.txt"), vec!["/path/to", "/path", "/"]);
+        assert_eq!(ancestors("//path/to"), vec!["//path", "//"]);
+        assert_eq!(ancestors("path/to/"), vec!["path", "."]);
+        assert!(ancestors("/").is_empty());
+        assert!(ancestors(".").is_empty());
+    }
+}
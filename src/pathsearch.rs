@@ -0,0 +1,178 @@
+//! `PATH` lookups in the style of `shutil.which`, with the listing of each
+//! search directory cached until its mtime changes.
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, OsString};
+use std::os::unix::fs::MetadataExt;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{_expanduser, _expandvars, _inner_join, _isabs, _normpath};
+
+/// `os.defpath`, used when neither `PATH` nor `confstr` has a path.
+const DEFPATH: &str = "/bin:/usr/bin";
+const PATHSEP: char = ':';
+
+// not in every `libc` release this crate allows
+extern "C" {
+    fn confstr(name: libc::c_int, buf: *mut libc::c_char, len: libc::size_t) -> libc::size_t;
+}
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const CS_PATH: libc::c_int = 0;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const CS_PATH: libc::c_int = 1;
+
+/// Listings younger than this are not cached: a change within the same
+/// mtime tick would otherwise go unnoticed.
+const MIN_AGE_SECS: i64 = 2;
+
+struct Listing {
+    mtime: (i64, i64),
+    names: Arc<HashSet<OsString>>,
+}
+
+static LISTINGS: OnceLock<Mutex<HashMap<String, Listing>>> = OnceLock::new();
+
+/// `shutil._access_check`: an existing non-directory accessible with `mode`.
+pub fn access_check(path_str: &str, mode: i32) -> bool {
+    let meta = match std::fs::metadata(path_str) {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+    let c_path = match CString::new(path_str) {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+    !meta.is_dir() && unsafe { libc::access(c_path.as_ptr(), mode) } == 0
+}
+
+/// Names in `dir`, from the cache when the directory hasn't changed since
+/// it was listed. `None` when `dir` can't be listed.
+fn listing(dir: &str) -> Option<Arc<HashSet<OsString>>> {
+    let meta = std::fs::metadata(dir).ok()?;
+    let mtime = (meta.mtime(), meta.mtime_nsec());
+    let cache = LISTINGS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(listing) = cache.lock().unwrap_or_else(|e| e.into_inner()).get(dir) {
+        if listing.mtime == mtime {
+            return Some(listing.names.clone());
+        }
+    }
+    let names: HashSet<OsString> = std::fs::read_dir(dir).ok()?.flatten().map(|x| x.file_name()).collect();
+    let names = Arc::new(names);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    if now - mtime.0 >= MIN_AGE_SECS {
+        let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.insert(dir.to_string(), Listing { mtime, names: names.clone() });
+    }
+    Some(names)
+}
+
+/// The search path when `PATH` isn't set: `confstr(_CS_PATH)` like
+/// `shutil.which`, or `os.defpath` when that's unavailable.
+pub fn default_path() -> String {
+    let len = unsafe { confstr(CS_PATH, std::ptr::null_mut(), 0) };
+    if len > 0 {
+        let mut buf = vec![0u8; len];
+        let n = unsafe { confstr(CS_PATH, buf.as_mut_ptr() as *mut libc::c_char, len) };
+        if n == len {
+            buf.truncate(len - 1);
+            if let Ok(path) = String::from_utf8(buf) {
+                return path;
+            }
+        }
+    }
+    DEFPATH.to_string()
+}
+
+pub fn clear_cache() {
+    if let Some(cache) = LISTINGS.get() {
+        cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+/// Every `dir/cmd` on `path` that passes `access_check`, in search order;
+/// only the first one when `first_only`. `~` entries are expanded before
+/// repeats are skipped, and absolute directories that don't list `cmd`
+/// are skipped without a stat.
+pub fn which_all(cmd: &str, mode: i32, path: &str, first_only: bool) -> Vec<String> {
+    let mut ret = vec![];
+    if path.is_empty() {
+        return ret;
+    }
+    let mut seen = HashSet::new();
+    for dir in path.split(PATHSEP) {
+        let dir = if dir.starts_with('~') { _expanduser(dir) } else { dir.to_string() };
+        if !seen.insert(dir.clone()) {
+            continue;
+        }
+        if _isabs(&dir) {
+            match listing(&dir) {
+                Some(names) if names.contains(std::ffi::OsStr::new(cmd)) => {}
+                _ => continue,
+            }
+        }
+        let name = if dir.is_empty() { cmd.to_string() } else { _inner_join(&dir, &[cmd]) };
+        if access_check(&name, mode) {
+            ret.push(name);
+            if first_only {
+                break;
+            }
+        }
+    }
+    ret
+}
+
+/// Entries of a search path. Empty entries stand for the current directory
+/// and are kept as `""`; an empty string has no entries at all.
+pub fn split_pathlist(path: &str) -> Vec<&str> {
+    if path.is_empty() {
+        return vec![];
+    }
+    path.split(PATHSEP).collect()
+}
+
+pub fn join_pathlist(entries: &[String]) -> Result<String, String> {
+    if let Some(e) = entries.iter().find(|x| x.contains(PATHSEP)) {
+        return Err(format!("entry contains {:?}: {:?}", PATHSEP, e));
+    }
+    Ok(entries.join(&PATHSEP.to_string()))
+}
+
+/// Normalize every entry and drop repeats, keeping the first occurrence.
+pub fn normalize_pathlist<'a, I: IntoIterator<Item = &'a str>>(entries: I) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut ret = vec![];
+    for e in entries {
+        let e = _normpath(e);
+        if seen.insert(e.clone()) {
+            ret.push(e);
+        }
+    }
+    ret
+}
+
+pub fn expand_entry(entry: &str) -> String {
+    let entry = if entry.starts_with('~') { _expanduser(entry) } else { entry.to_string() };
+    if entry.contains('$') {
+        _expandvars(&entry)
+    } else {
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_path, join_pathlist, normalize_pathlist, split_pathlist};
+
+    #[test]
+    fn pathlist() {
+        assert_eq!(split_pathlist("/a::/b:"), vec!["/a", "", "/b", ""]);
+        assert!(split_pathlist("").is_empty());
+        assert_eq!(split_pathlist(":"), vec!["", ""]);
+        let entries: Vec<String> = split_pathlist("/a::/b").into_iter().map(String::from).collect();
+        assert_eq!(join_pathlist(&entries), Ok("/a::/b".to_string()));
+        assert!(join_pathlist(&["/a:/b".to_string()]).is_err());
+        assert_eq!(normalize_pathlist(["/a/", "b/../c", "/a", "."]), vec!["/a", "c", "."]);
+        assert_eq!(normalize_pathlist(["/a", "", "."]), vec!["/a", "."]);
+        assert!(!default_path().is_empty());
+    }
+}
@@ -0,0 +1,531 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::_split_parts;
+
+const CACHE_SIZE: usize = 256;
+
+/// Small LRU cache for compiled patterns. `order` maps each entry's last
+/// use to its key, so the oldest one is evicted without a scan.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    tick: u64,
+    map: HashMap<K, (Arc<V>, u64)>,
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Eq + Hash + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        LruCache { capacity, tick: 0, map: HashMap::new(), order: BTreeMap::new() }
+    }
+
+    pub fn get_or_insert_with<F>(&mut self, key: &K, f: F) -> Arc<V>
+    where
+        F: FnOnce() -> V,
+    {
+        self.tick += 1;
+        if let Some(entry) = self.map.get_mut(key) {
+            let k = self.order.remove(&entry.1).expect("LRU order out of sync");
+            self.order.insert(self.tick, k);
+            entry.1 = self.tick;
+            return entry.0.clone();
+        }
+        if self.map.len() >= self.capacity {
+            if let Some((_, k)) = self.order.pop_first() {
+                self.map.remove(&k);
+            }
+        }
+        let value = Arc::new(f());
+        self.map.insert(key.clone(), (value.clone(), self.tick));
+        self.order.insert(self.tick, key.clone());
+        value
+    }
+}
+
+#[derive(Debug)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    Class(bool, Vec<(char, char)>),
+}
+
+impl Token {
+    #[inline(always)]
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(x) => *x == c,
+            Token::Any => true,
+            Token::Star => false,
+            Token::Class(negated, ranges) => ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated,
+        }
+    }
+}
+
+/// Parse a `[...]` class starting at `chars[i] == '['`. Returns the token and
+/// the index just past the closing `]`, or `None` when the class is unclosed.
+fn parse_class(chars: &[char], i: usize) -> Option<(Token, usize)> {
+    let n = chars.len();
+    let mut j = i + 1;
+    if j < n && chars[j] == '!' {
+        j += 1;
+    }
+    if j < n && chars[j] == ']' {
+        j += 1;
+    }
+    while j < n && chars[j] != ']' {
+        j += 1;
+    }
+    if j >= n {
+        return None;
+    }
+    let mut stuff = &chars[i + 1..j];
+    let negated = stuff.first() == Some(&'!');
+    if negated {
+        stuff = &stuff[1..];
+    }
+    let mut ranges = vec![];
+    let mut k = 0;
+    while k < stuff.len() {
+        if k + 2 < stuff.len() && stuff[k + 1] == '-' {
+            // reversed ranges such as `z-a` match nothing, like CPython's fnmatch
+            if stuff[k] <= stuff[k + 2] {
+                ranges.push((stuff[k], stuff[k + 2]));
+            }
+            k += 3;
+        } else {
+            ranges.push((stuff[k], stuff[k]));
+            k += 1;
+        }
+    }
+    Some((Token::Class(negated, ranges), j + 1))
+}
+
+/// A single glob component (`*`, `?` and `[seq]`), matched against one path
+/// component at a time.
+#[derive(Debug)]
+pub struct Segment {
+    tokens: Vec<Token>,
+    literal: Option<String>,
+    case_sensitive: bool,
+}
+
+impl Segment {
+    pub fn compile(pat: &str, case_sensitive: bool) -> Segment {
+        let folded;
+        let pat = if case_sensitive {
+            pat
+        } else {
+            folded = pat.to_lowercase();
+            folded.as_str()
+        };
+        let chars: Vec<char> = pat.chars().collect();
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '*' => {
+                    if !matches!(tokens.last(), Some(Token::Star)) {
+                        tokens.push(Token::Star);
+                    }
+                    i += 1;
+                }
+                '?' => {
+                    tokens.push(Token::Any);
+                    i += 1;
+                }
+                '[' => match parse_class(&chars, i) {
+                    Some((token, j)) => {
+                        tokens.push(token);
+                        i = j;
+                    }
+                    None => {
+                        tokens.push(Token::Char('['));
+                        i += 1;
+                    }
+                },
+                c => {
+                    tokens.push(Token::Char(c));
+                    i += 1;
+                }
+            }
+        }
+        let literal = if tokens.iter().all(|x| matches!(x, Token::Char(_))) {
+            Some(pat.to_string())
+        } else {
+            None
+        };
+        Segment { tokens, literal, case_sensitive }
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        let folded;
+        let name = if self.case_sensitive {
+            name
+        } else {
+            folded = name.to_lowercase();
+            folded.as_str()
+        };
+        if let Some(ref literal) = self.literal {
+            return literal == name;
+        }
+        let chars: Vec<char> = name.chars().collect();
+        let (mut t, mut c) = (0, 0);
+        let mut backtrack: Option<(usize, usize)> = None;
+        while c < chars.len() {
+            if t < self.tokens.len() {
+                if let Token::Star = self.tokens[t] {
+                    backtrack = Some((t, c));
+                    t += 1;
+                    continue;
+                }
+                if self.tokens[t].matches(chars[c]) {
+                    t += 1;
+                    c += 1;
+                    continue;
+                }
+            }
+            match backtrack {
+                Some((bt, bc)) => {
+                    backtrack = Some((bt, bc + 1));
+                    t = bt + 1;
+                    c = bc + 1;
+                }
+                None => return false,
+            }
+        }
+        self.tokens[t..].iter().all(|x| matches!(x, Token::Star))
+    }
+}
+
+#[derive(Debug)]
+enum PathSeg {
+    Segment(Segment),
+    Recursive,
+}
+
+/// A compiled path pattern: an anchor and one glob per component, where a
+/// `**` component matches any number of path components.
+#[derive(Debug)]
+pub struct PathPattern {
+    anchor: String,
+    segs: Vec<PathSeg>,
+}
+
+impl PathPattern {
+    pub fn compile(pattern: &str, case_sensitive: bool) -> PathPattern {
+        let (anchor, parts) = _split_parts(pattern);
+        let segs = parts
+            .into_iter()
+            .map(|x| {
+                if x == "**" {
+                    PathSeg::Recursive
+                } else {
+                    PathSeg::Segment(Segment::compile(x, case_sensitive))
+                }
+            })
+            .collect();
+        PathPattern { anchor: anchor.to_string(), segs }
+    }
+
+    /// True for a pattern with neither an anchor nor a component, such
+    /// as "" or ".", which `PurePath.match` rejects.
+    pub fn is_empty(&self) -> bool {
+        self.anchor.is_empty() && self.segs.is_empty()
+    }
+
+    /// Match `parts` against the compiled segments, optionally behind an
+    /// implicit leading `**`.
+    fn match_parts(&self, parts: &[&str], leading_recursive: bool) -> bool {
+        const RECURSIVE: PathSeg = PathSeg::Recursive;
+        let offset = leading_recursive as usize;
+        let seg_at = |s: usize| -> Option<&PathSeg> {
+            if s < offset {
+                Some(&RECURSIVE)
+            } else {
+                self.segs.get(s - offset)
+            }
+        };
+        let (mut s, mut p) = (0, 0);
+        let mut backtrack: Option<(usize, usize)> = None;
+        while p < parts.len() {
+            match seg_at(s) {
+                Some(PathSeg::Recursive) => {
+                    backtrack = Some((s, p));
+                    s += 1;
+                    continue;
+                }
+                Some(PathSeg::Segment(seg)) if seg.is_match(parts[p]) => {
+                    s += 1;
+                    p += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match backtrack {
+                Some((bs, bp)) => {
+                    backtrack = Some((bs, bp + 1));
+                    s = bs + 1;
+                    p = bp + 1;
+                }
+                None => return false,
+            }
+        }
+        (s.saturating_sub(offset)..self.segs.len()).all(|x| matches!(self.segs[x], PathSeg::Recursive))
+    }
+
+    /// Match the whole path. A relative pattern starting with `**` also
+    /// matches absolute paths.
+    pub fn full_match(&self, path_str: &str) -> bool {
+        let (anchor, parts) = _split_parts(path_str);
+        if anchor != self.anchor && !(self.anchor.is_empty() && matches!(self.segs.first(), Some(PathSeg::Recursive))) {
+            return false;
+        }
+        self.match_parts(&parts, false)
+    }
+
+    /// Match like `PurePath.match`: relative patterns match from the right,
+    /// absolute patterns must match the whole path.
+    pub fn match_right(&self, path_str: &str) -> bool {
+        if !self.anchor.is_empty() {
+            return self.full_match(path_str);
+        }
+        let (_, parts) = _split_parts(path_str);
+        self.match_parts(&parts, true)
+    }
+}
+
+static PATH_PATTERN_CACHE: OnceLock<Mutex<LruCache<(String, bool), PathPattern>>> = OnceLock::new();
+
+/// Compile `pattern`, reusing a cached compilation when there is one.
+pub fn compile_path_pattern(pattern: &str, case_sensitive: bool) -> Arc<PathPattern> {
+    let cache = PATH_PATTERN_CACHE.get_or_init(|| Mutex::new(LruCache::new(CACHE_SIZE)));
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    cache.get_or_insert_with(&(pattern.to_string(), case_sensitive), || {
+        PathPattern::compile(pattern, case_sensitive)
+    })
+}
+
+const FNMATCH_CACHE_SIZE: usize = 32768;
+
+static FNMATCH_CACHE: OnceLock<Mutex<LruCache<String, Segment>>> = OnceLock::new();
+
+/// Compile a case-sensitive fnmatch pattern, reusing a cached compilation
+/// when there is one.
+pub fn compile_fnmatch(pattern: &str) -> Arc<Segment> {
+    let cache = FNMATCH_CACHE.get_or_init(|| Mutex::new(LruCache::new(FNMATCH_CACHE_SIZE)));
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    cache.get_or_insert_with(&pattern.to_string(), || Segment::compile(pattern, true))
+}
+
+fn re_escape(c: char, out: &mut String) {
+    if "()[]{}?*+-|^$\\.&~# \t\n\r\x0b\x0c".contains(c) {
+        out.push('\\');
+    }
+    out.push(c);
+}
+
+/// Port of CPython's `fnmatch.translate`, producing the same regular
+/// expression text. Python 3.11+ uses atomic groups; before that each
+/// group is emulated with a lookahead and a named backreference, numbered
+/// by `next_group`.
+pub fn translate<E>(pat: &str, mut next_group: Option<&mut dyn FnMut() -> Result<usize, E>>) -> Result<String, E> {
+    enum Item {
+        Star,
+        Text(String),
+    }
+    let pat: Vec<char> = pat.chars().collect();
+    let find = |c: char, from: usize, to: usize| (from..to).find(|&x| pat[x] == c);
+    let n = pat.len();
+    let mut res: Vec<Item> = vec![];
+    let mut i = 0;
+    while i < n {
+        let c = pat[i];
+        i += 1;
+        match c {
+            '*' => {
+                if !matches!(res.last(), Some(Item::Star)) {
+                    res.push(Item::Star);
+                }
+            }
+            '?' => res.push(Item::Text(".".to_string())),
+            '[' => {
+                let mut j = i;
+                if j < n && pat[j] == '!' {
+                    j += 1;
+                }
+                if j < n && pat[j] == ']' {
+                    j += 1;
+                }
+                while j < n && pat[j] != ']' {
+                    j += 1;
+                }
+                if j >= n {
+                    res.push(Item::Text("\\[".to_string()));
+                    continue;
+                }
+                let mut stuff: String = if find('-', i, j).is_none() {
+                    pat[i..j].iter().collect::<String>().replace('\\', "\\\\")
+                } else {
+                    let mut chunks: Vec<Vec<char>> = vec![];
+                    let mut k = if pat[i] == '!' { i + 2 } else { i + 1 };
+                    while let Some(found) = find('-', k.min(j), j) {
+                        chunks.push(pat[i..found].to_vec());
+                        i = found + 1;
+                        k = found + 3;
+                    }
+                    let chunk = pat[i..j].to_vec();
+                    if !chunk.is_empty() {
+                        chunks.push(chunk);
+                    } else if let Some(last) = chunks.last_mut() {
+                        last.push('-');
+                    }
+                    for k in (1..chunks.len()).rev() {
+                        if chunks[k - 1].last() > chunks[k].first() {
+                            let mut merged = chunks[k - 1][..chunks[k - 1].len() - 1].to_vec();
+                            merged.extend_from_slice(&chunks[k][1..]);
+                            chunks[k - 1] = merged;
+                            chunks.remove(k);
+                        }
+                    }
+                    chunks
+                        .iter()
+                        .map(|x| x.iter().collect::<String>().replace('\\', "\\\\").replace('-', "\\-"))
+                        .collect::<Vec<String>>()
+                        .join("-")
+                };
+                let mut escaped = String::with_capacity(stuff.len());
+                for c in stuff.chars() {
+                    if c == '&' || c == '~' || c == '|' {
+                        escaped.push('\\');
+                    }
+                    escaped.push(c);
+                }
+                stuff = escaped;
+                i = j + 1;
+                if stuff.is_empty() {
+                    res.push(Item::Text("(?!)".to_string()));
+                } else if stuff == "!" {
+                    res.push(Item::Text(".".to_string()));
+                } else {
+                    if let Some(rest) = stuff.strip_prefix('!') {
+                        stuff = format!("^{}", rest);
+                    } else if stuff.starts_with('^') || stuff.starts_with('[') {
+                        stuff = format!("\\{}", stuff);
+                    }
+                    res.push(Item::Text(format!("[{}]", stuff)));
+                }
+            }
+            c => {
+                let mut text = String::new();
+                re_escape(c, &mut text);
+                res.push(Item::Text(text));
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let mut items = res.into_iter().peekable();
+    while let Some(Item::Text(text)) = items.next_if(|x| matches!(x, Item::Text(_))) {
+        out.push_str(&text);
+    }
+    while items.next().is_some() {
+        let mut fixed = String::new();
+        while let Some(Item::Text(text)) = items.next_if(|x| matches!(x, Item::Text(_))) {
+            fixed.push_str(&text);
+        }
+        if items.peek().is_none() {
+            out.push_str(".*");
+            out.push_str(&fixed);
+        } else if let Some(next_group) = next_group.as_mut() {
+            let n = next_group()?;
+            out.push_str(&format!("(?=(?P<g{}>.*?{}))(?P=g{})", n, fixed, n));
+        } else {
+            out.push_str(&format!("(?>.*?{})", fixed));
+        }
+    }
+    Ok(format!("(?s:{})\\Z", out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{translate, LruCache, PathPattern, Segment};
+
+    #[test]
+    fn segment() {
+        assert!(Segment::compile("*.py", true).is_match("setup.py"));
+        assert!(Segment::compile("*.py", true).is_match(".py"));
+        assert!(!Segment::compile("*.py", true).is_match("setup.pyc"));
+        assert!(Segment::compile("a?c", true).is_match("abc"));
+        assert!(Segment::compile("[a-c]x[!0-9]", true).is_match("bxz"));
+        assert!(!Segment::compile("[a-c]x[!0-9]", true).is_match("bx1"));
+        assert!(Segment::compile("[]]", true).is_match("]"));
+        assert!(Segment::compile("[!]]", true).is_match("a"));
+        assert!(Segment::compile("[abc", true).is_match("[abc"));
+        assert!(!Segment::compile("[z-a]", true).is_match("m"));
+        assert!(Segment::compile("*a*b*", true).is_match("xxaxxbxx"));
+        assert!(Segment::compile("README*", false).is_match("readme.md"));
+        assert!(!Segment::compile("README*", true).is_match("readme.md"));
+        assert_eq!(Segment::compile("setup.py", true).literal.as_deref(), Some("setup.py"));
+    }
+
+    #[test]
+    fn path_pattern() {
+        let p = PathPattern::compile("/src/**/*.rs", true);
+        assert!(p.full_match("/src/lib.rs"));
+        assert!(p.full_match("/src/a/b/lib.rs"));
+        assert!(!p.full_match("src/lib.rs"));
+        assert!(!p.full_match("/src/lib.py"));
+
+        let p = PathPattern::compile("**/*.py", true);
+        assert!(p.full_match("a/b.py"));
+        assert!(p.full_match("/a/b.py"));
+        assert!(p.full_match("b.py"));
+
+        let p = PathPattern::compile("b/*.py", true);
+        assert!(p.match_right("/a/b/c.py"));
+        assert!(!p.full_match("/a/b/c.py"));
+        assert!(!p.match_right("/a/c.py"));
+
+        let p = PathPattern::compile("/*.py", true);
+        assert!(p.match_right("/c.py"));
+        assert!(!p.match_right("/a/c.py"));
+    }
+
+    #[test]
+    fn lru_cache() {
+        let mut cache: LruCache<u32, u32> = LruCache::new(2);
+        cache.get_or_insert_with(&1, || 10);
+        cache.get_or_insert_with(&2, || 20);
+        cache.get_or_insert_with(&1, || 0);
+        cache.get_or_insert_with(&3, || 30);
+        assert_eq!(*cache.get_or_insert_with(&1, || 0), 10);
+        assert_eq!(*cache.get_or_insert_with(&2, || 0), 0);
+        assert_eq!(cache.map.len(), 2);
+        assert_eq!(cache.order.len(), 2);
+        assert_eq!(*cache.get_or_insert_with(&1, || 0), 10);
+        assert_eq!(*cache.get_or_insert_with(&3, || 0), 0);
+    }
+
+    #[test]
+    fn test_translate() {
+        let atomic = |p: &str| translate::<()>(p, None).unwrap();
+        assert_eq!(atomic("*"), r"(?s:.*)\Z");
+        assert_eq!(atomic("*.py"), r"(?s:.*\.py)\Z");
+        assert_eq!(atomic("a*b*c"), r"(?s:a(?>.*?b).*c)\Z");
+        assert_eq!(atomic("[!a-c]?"), r"(?s:[^a-c].)\Z");
+        assert_eq!(atomic("[z-a]"), r"(?s:(?!))\Z");
+        assert_eq!(atomic("[c-b-a]"), r"(?s:[\-a])\Z");
+        assert_eq!(atomic("[a&&b]"), r"(?s:[a\&\&b])\Z");
+        assert_eq!(atomic("[abc"), r"(?s:\[abc)\Z");
+
+        let mut n = 0;
+        let mut next_group = || -> Result<usize, ()> {
+            n += 1;
+            Ok(n)
+        };
+        let ret = translate(r"a*b*c*d", Some(&mut next_group)).unwrap();
+        assert_eq!(ret, r"(?s:a(?=(?P<g1>.*?b))(?P=g1)(?=(?P<g2>.*?c))(?P=g2).*d)\Z");
+    }
+}
@@ -0,0 +1,210 @@
+//! Resolved `realpath` prefixes, shared across calls.
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+
+use crate::_realpath;
+use crate::at;
+use crate::utils::pyobj2str;
+use crate::vlinks::VirtualLinks;
+
+/// What a path component turned out to be: `None` when it isn't a
+/// symlink, otherwise the link text. Errors come from `lstat`, or from
+/// `readlink` when the link was replaced in between.
+pub type LinkInfo = io::Result<Option<OsString>>;
+
+pub fn lstat_link(path: &str) -> LinkInfo {
+    let meta = std::fs::symlink_metadata(path)?;
+    if !meta.file_type().is_symlink() {
+        return Ok(None);
+    }
+    Ok(Some(std::fs::read_link(path)?.into_os_string()))
+}
+
+/// Where `realpath` looks its components up.
+#[derive(Clone, Copy)]
+pub enum LinkSource<'a> {
+    /// The filesystem, skipping prefixes a `PrefixCache` already resolved.
+    Fs(Option<&'a PrefixCache>),
+    /// The filesystem, with relative paths taken from a directory fd.
+    At(RawFd),
+    /// A map of symlinks that never touches the disk.
+    Virtual(&'a VirtualLinks),
+}
+
+impl LinkSource<'_> {
+    pub fn lookup(&self, path: &str) -> LinkInfo {
+        match self {
+            LinkSource::Fs(_) => lstat_link(path),
+            LinkSource::At(fd) => at::readlink_at(Some(*fd), OsStr::new(path)),
+            LinkSource::Virtual(links) => links.lookup(path),
+        }
+    }
+
+    /// What `path` was last found to resolve to under `root`; `Some(None)`
+    /// when it isn't a symlink.
+    pub fn resolved(&self, root: &str, path: &str) -> Option<Option<String>> {
+        match self {
+            LinkSource::Fs(Some(cache)) => cache.get(root, path),
+            _ => None,
+        }
+    }
+
+    pub fn remember(&self, root: &str, learned: Vec<(String, Option<String>)>) {
+        if let LinkSource::Fs(Some(cache)) = self {
+            cache.insert(root, learned);
+        }
+    }
+
+    /// The error strict mode raises for a loop found at `path`. `stat`
+    /// reports the loop itself; should the link have been fixed in the
+    /// meantime, it's ELOOP all the same.
+    pub fn loop_error(&self, path: &str) -> io::Error {
+        match self {
+            LinkSource::Fs(_) => match std::fs::metadata(path) {
+                Err(e) => e,
+                Ok(_) => io::Error::from_raw_os_error(libc::ELOOP),
+            },
+            LinkSource::At(fd) => match at::stat_at(Some(*fd), OsStr::new(path), true) {
+                Err(e) => e,
+                Ok(_) => io::Error::from_raw_os_error(libc::ELOOP),
+            },
+            LinkSource::Virtual(_) => io::Error::from_raw_os_error(libc::ELOOP),
+        }
+    }
+}
+
+struct Entry {
+    resolved: Option<String>,
+    stamp: Instant,
+}
+
+/// What each absolute prefix resolved to, keyed by `root` and then by the
+/// prefix, spelled as its resolved parent plus the next name. A prefix
+/// that isn't a symlink maps to `None`, so a hit saves its `lstat`; a
+/// symlink maps to its fully resolved path, which saves every lookup the
+/// target took. Only prefixes resolved without an error are kept.
+pub struct PrefixCache {
+    ttl: Option<Duration>,
+    entries: Mutex<HashMap<String, HashMap<String, Entry>>>,
+}
+
+impl PrefixCache {
+    pub fn new(ttl: Option<Duration>) -> Self {
+        PrefixCache { ttl, entries: Mutex::new(HashMap::new()) }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, HashMap<String, Entry>>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get(&self, root: &str, path: &str) -> Option<Option<String>> {
+        let entries = self.entries();
+        let entry = entries.get(root)?.get(path)?;
+        if self.ttl.is_some_and(|ttl| entry.stamp.elapsed() >= ttl) {
+            return None;
+        }
+        Some(entry.resolved.clone())
+    }
+
+    pub fn insert(&self, root: &str, learned: Vec<(String, Option<String>)>) {
+        if learned.is_empty() {
+            return;
+        }
+        let stamp = Instant::now();
+        let mut entries = self.entries();
+        let entries = entries.entry(root.to_string()).or_default();
+        for (path, resolved) in learned {
+            entries.insert(path, Entry { resolved, stamp });
+        }
+    }
+
+    pub fn clear(&self) {
+        self.entries().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().values().map(|x| x.len()).sum()
+    }
+}
+
+/// Opt-in cache for `realpath`, passed as `realpath(path, cache=...)` or
+/// used through its own `realpath` method. Resolved prefixes are reused
+/// until `clear()` or until `ttl` seconds have passed, whatever changes on
+/// disk in the meantime.
+#[pyclass(module = "fpath._fpath")]
+pub struct RealpathCache {
+    pub cache: PrefixCache,
+}
+
+#[pymethods]
+impl RealpathCache {
+    #[new]
+    #[pyo3(signature = (ttl=None))]
+    fn new(ttl: Option<f64>) -> PyResult<Self> {
+        let ttl = match ttl {
+            Some(t) if t < 0.0 || !t.is_finite() => {
+                return Err(exceptions::PyValueError::new_err("ttl must be a non-negative number"));
+            }
+            t => t.map(Duration::from_secs_f64),
+        };
+        Ok(RealpathCache { cache: PrefixCache::new(ttl) })
+    }
+
+    #[pyo3(signature = (path, *, strict=false, root=None))]
+    fn realpath(&self, py: Python, path: &PyAny, strict: bool, root: Option<&PyAny>) -> PyResult<PyObject> {
+        let (path_str, is_bytes) = pyobj2str(&py, path).map_err(exceptions::PyTypeError::new_err)?;
+        let root = match root {
+            Some(r) => {
+                let (root, root_is_bytes) = pyobj2str(&py, r).map_err(exceptions::PyTypeError::new_err)?;
+                if root_is_bytes != is_bytes {
+                    return Err(exceptions::PyTypeError::new_err("Can't mix strings and bytes in path components"));
+                }
+                Some(root)
+            }
+            None => None,
+        };
+        let ret = _realpath(&path_str, strict, Some(&self.cache), root.as_deref())?;
+        str2pyobj!(py, &ret, is_bytes)
+    }
+
+    fn clear(&self) {
+        self.cache.clear();
+    }
+
+    fn __len__(&self) -> usize {
+        self.cache.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lstat_link, PrefixCache};
+    use std::time::Duration;
+
+    #[test]
+    fn prefixes() {
+        let cache = PrefixCache::new(None);
+        cache.insert("", vec![("/a".to_string(), None), ("/a/link".to_string(), Some("/b".to_string()))]);
+        cache.insert("/jail", vec![("/a".to_string(), Some("/c".to_string()))]);
+        assert_eq!(cache.get("", "/a"), Some(None));
+        assert_eq!(cache.get("", "/a/link"), Some(Some("/b".to_string())));
+        assert_eq!(cache.get("/jail", "/a"), Some(Some("/c".to_string())));
+        assert_eq!(cache.get("", "/b"), None);
+        assert_eq!(cache.len(), 3);
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+        assert_eq!(lstat_link("/").ok(), Some(None));
+
+        let cache = PrefixCache::new(Some(Duration::ZERO));
+        cache.insert("", vec![("/a".to_string(), None)]);
+        assert_eq!(cache.get("", "/a"), None);
+    }
+}
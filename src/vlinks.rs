@@ -0,0 +1,75 @@
+//! An in-memory stand-in for the filesystem, so `realpath` can run
+//! against a map of symlinks without touching the disk.
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io;
+
+use crate::rpcache::LinkInfo;
+
+/// Symlinks keyed by normalized absolute path, and optionally the paths
+/// known to exist. Without `existing` every path that isn't a link is
+/// taken to exist; with it, only those paths, their parents and the
+/// parents of the links do.
+pub struct VirtualLinks {
+    links: HashMap<String, OsString>,
+    existing: Option<HashSet<String>>,
+}
+
+fn parent(path: &str) -> Option<&str> {
+    match memchr::memrchr(b'/', path.as_bytes()) {
+        _ if path == "/" => None,
+        Some(0) => Some("/"),
+        Some(i) => Some(&path[..i]),
+        None => None,
+    }
+}
+
+impl VirtualLinks {
+    pub fn new(links: HashMap<String, String>, existing: Option<Vec<String>>) -> Self {
+        let existing = existing.map(|paths| {
+            let mut set = HashSet::new();
+            set.insert("/".to_string());
+            for path in paths.iter().map(|x| x.as_str()).chain(links.keys().map(|x| x.as_str())) {
+                let mut cur = Some(path);
+                while let Some(p) = cur {
+                    if !set.insert(p.to_string()) {
+                        break;
+                    }
+                    cur = parent(p);
+                }
+            }
+            set
+        });
+        let links = links.into_iter().map(|(k, v)| (k, OsString::from(v))).collect();
+        VirtualLinks { links, existing }
+    }
+
+    pub fn lookup(&self, path: &str) -> LinkInfo {
+        if let Some(target) = self.links.get(path) {
+            return Ok(Some(target.clone()));
+        }
+        match &self.existing {
+            Some(set) if !set.contains(path) => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtualLinks;
+    use std::collections::HashMap;
+
+    #[test]
+    fn lookup() {
+        let links = HashMap::from([("/a/l".to_string(), "b".to_string())]);
+        let v = VirtualLinks::new(links.clone(), Some(vec!["/a/b/c".to_string()]));
+        assert_eq!(v.lookup("/a/l").unwrap().unwrap(), "b");
+        assert!(v.lookup("/a").unwrap().is_none());
+        assert!(v.lookup("/a/b").unwrap().is_none());
+        assert!(v.lookup("/").unwrap().is_none());
+        assert!(v.lookup("/x").is_err());
+        let v = VirtualLinks::new(links, None);
+        assert!(v.lookup("/x").unwrap().is_none());
+    }
+}
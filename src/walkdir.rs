@@ -0,0 +1,471 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyList, PyTuple};
+
+use crate::finder::{self, Filter, Selected};
+use crate::ignore::Ignore;
+use crate::{_file_id, _inner_join};
+
+/// Maximum number of finished but not yet consumed directory listings kept
+/// around by eager (bottom-up) prefetching.
+const MAX_EAGER_RESULTS: usize = 4096;
+pub const MAX_THREADS: usize = 8;
+
+pub fn join_name(dirpath: &OsStr, name: &OsStr) -> OsString {
+    match (dirpath.to_str(), name.to_str()) {
+        (Some(d), Some(n)) => OsString::from(_inner_join(d, &[n])),
+        _ => {
+            let mut buf = dirpath.as_bytes().to_vec();
+            if !buf.is_empty() && !buf.ends_with(b"/") {
+                buf.push(b'/');
+            }
+            buf.extend_from_slice(name.as_bytes());
+            OsString::from_vec(buf)
+        }
+    }
+}
+
+pub fn os2py(py: Python, s: &OsStr, is_bytes: bool) -> PyObject {
+    if is_bytes {
+        PyBytes::new(py, s.as_bytes()).to_object(py)
+    } else {
+        s.to_object(py)
+    }
+}
+
+pub fn py2os(obj: &PyAny) -> PyResult<OsString> {
+    match obj.downcast::<PyBytes>() {
+        Ok(b) => Ok(OsString::from_vec(b.as_bytes().to_vec())),
+        Err(_) => obj.extract::<OsString>(),
+    }
+}
+
+/// Build the `OSError` subclass matching `e`, with `filename` set.
+pub fn oserror(e: &io::Error, filename: &OsStr) -> PyErr {
+    let filename = filename.to_string_lossy().into_owned();
+    match e.raw_os_error() {
+        Some(errno) => {
+            let msg = io::Error::from_raw_os_error(errno).to_string();
+            let msg = match msg.find(" (os error") {
+                Some(i) => msg[..i].to_string(),
+                None => msg,
+            };
+            exceptions::PyOSError::new_err((errno, msg, filename))
+        }
+        None => exceptions::PyOSError::new_err(format!("{}: '{}'", e, filename)),
+    }
+}
+
+/// Directory listing split the way `os.walk` does: `dirs` holds every entry
+/// for which `is_dir()` is true (following symlinks) with its symlink flag.
+pub struct Scan {
+    pub id: Option<(u64, u64)>,
+    pub dirs: Vec<(OsString, bool)>,
+    pub files: Vec<OsString>,
+}
+
+pub fn scan_dir(path: &OsStr, with_id: bool) -> io::Result<Scan> {
+    let id = if with_id {
+        std::fs::metadata(path).ok().map(|m| _file_id(&m))
+    } else {
+        None
+    };
+    let mut dirs = vec![];
+    let mut files = vec![];
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let (is_dir, is_symlink) = match entry.file_type() {
+            Ok(ft) if ft.is_symlink() => (std::fs::metadata(entry.path()).map(|m| m.is_dir()).unwrap_or(false), true),
+            Ok(ft) => (ft.is_dir(), false),
+            Err(_) => (false, false),
+        };
+        if is_dir {
+            dirs.push((name, is_symlink));
+        } else {
+            files.push(name);
+        }
+    }
+    Ok(Scan { id, dirs, files })
+}
+
+/// Drop the entries of `scan` that `ignore` excludes. Symlinks count as
+/// files, the way git sees them.
+pub fn filter_scan(scan: &mut Scan, path: &OsStr, ignore: &Ignore) {
+    if let Some(is_ignored) = ignore.entry_filter(path) {
+        scan.dirs.retain(|(name, is_symlink)| !is_ignored(name, !is_symlink));
+        scan.files.retain(|name| !is_ignored(name, false));
+    }
+}
+
+#[derive(Default)]
+struct PoolState {
+    queue: VecDeque<OsString>,
+    requested: HashSet<OsString>,
+    results: HashMap<OsString, io::Result<Scan>>,
+    shutdown: bool,
+}
+
+/// Worker threads reading directories ahead of the consumer.
+pub struct Pool {
+    state: Mutex<PoolState>,
+    job_cv: Condvar,
+    done_cv: Condvar,
+    with_id: bool,
+    eager: bool,
+    followlinks: bool,
+    ignore: Option<Arc<Ignore>>,
+}
+
+impl Pool {
+    pub fn new(with_id: bool, eager: bool, followlinks: bool, ignore: Option<Arc<Ignore>>) -> Arc<Pool> {
+        let pool = Arc::new(Pool {
+            state: Mutex::new(PoolState::default()),
+            job_cv: Condvar::new(),
+            done_cv: Condvar::new(),
+            with_id,
+            eager,
+            followlinks,
+            ignore,
+        });
+        let nthreads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(MAX_THREADS);
+        for _ in 0..nthreads {
+            let pool = pool.clone();
+            thread::spawn(move || pool.work());
+        }
+        pool
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn work(&self) {
+        loop {
+            let path = {
+                let mut state = self.lock();
+                loop {
+                    if state.shutdown {
+                        return;
+                    }
+                    match state.queue.pop_front() {
+                        Some(p) if state.requested.contains(&p) => break p,
+                        Some(_) => continue,
+                        None => state = self.job_cv.wait(state).unwrap_or_else(|e| e.into_inner()),
+                    }
+                }
+            };
+            let mut result = scan_dir(&path, self.with_id);
+            if let (Ok(scan), Some(ignore)) = (result.as_mut(), self.ignore.as_ref()) {
+                filter_scan(scan, &path, ignore);
+            }
+            let mut state = self.lock();
+            if !state.requested.contains(&path) {
+                continue;
+            }
+            if self.eager && state.results.len() < MAX_EAGER_RESULTS {
+                if let Ok(ref scan) = result {
+                    for (name, is_symlink) in scan.dirs.iter() {
+                        if *is_symlink {
+                            continue;
+                        }
+                        let child = join_name(&path, name);
+                        if state.requested.insert(child.clone()) {
+                            state.queue.push_back(child);
+                        }
+                    }
+                    self.job_cv.notify_all();
+                }
+            }
+            state.results.insert(path, result);
+            self.done_cv.notify_all();
+        }
+    }
+
+    pub fn request<I: IntoIterator<Item = OsString>>(&self, paths: I) {
+        let mut state = self.lock();
+        for p in paths {
+            if state.requested.insert(p.clone()) {
+                state.queue.push_back(p);
+            }
+        }
+        self.job_cv.notify_all();
+    }
+
+    /// Block until the listing for `path` is available, reading it with
+    /// priority if nobody asked for it yet.
+    pub fn take(&self, path: &OsStr) -> io::Result<Scan> {
+        let mut state = self.lock();
+        if state.requested.insert(path.to_os_string()) {
+            state.queue.push_front(path.to_os_string());
+            self.job_cv.notify_all();
+        } else if let Some(i) = state.queue.iter().position(|p| p == path) {
+            let p = state.queue.remove(i).unwrap();
+            state.queue.push_front(p);
+        }
+        loop {
+            if let Some(result) = state.results.remove(path) {
+                state.requested.remove(path);
+                return result;
+            }
+            state = self.done_cv.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    pub fn cancel(&self, path: &OsStr) {
+        let mut state = self.lock();
+        state.requested.remove(path);
+        state.results.remove(path);
+    }
+
+    pub fn shutdown(&self) {
+        self.lock().shutdown = true;
+        self.job_cv.notify_all();
+    }
+
+    pub fn followlinks(&self) -> bool {
+        self.followlinks
+    }
+}
+
+/// Chain of `(dev, ino)` identities of the directories above the one being
+/// walked, used to stop symlink loops when following links.
+pub struct Ancestor {
+    id: (u64, u64),
+    parent: Option<Arc<Ancestor>>,
+}
+
+impl Ancestor {
+    pub fn contains(node: &Option<Arc<Ancestor>>, id: (u64, u64)) -> bool {
+        let mut node = node.as_ref();
+        while let Some(n) = node {
+            if n.id == id {
+                return true;
+            }
+            node = n.parent.as_ref();
+        }
+        false
+    }
+
+    pub fn push(node: Option<Arc<Ancestor>>, id: (u64, u64)) -> Option<Arc<Ancestor>> {
+        Some(Arc::new(Ancestor { id, parent: node }))
+    }
+}
+
+enum Frame {
+    Visit(OsString, Option<Arc<Ancestor>>, usize),
+    Yield(OsString, Vec<OsString>, Vec<OsString>),
+}
+
+/// A top-down result whose `dirnames` list the caller may still prune.
+struct Pending {
+    dirpath: OsString,
+    dirnames: Py<PyList>,
+    symlinks: HashMap<OsString, bool>,
+    prefetched: Vec<OsString>,
+    ancestors: Option<Arc<Ancestor>>,
+    depth: usize,
+    // with a filter: the names in `dirnames` that may be walked into, and
+    // the subdirectories walked into without being listed
+    filtered: Option<(HashSet<OsString>, Vec<OsString>)>,
+}
+
+#[pyclass(module = "fpath._fpath")]
+pub struct Walk {
+    pool: Arc<Pool>,
+    stack: Vec<Frame>,
+    pending: Option<Pending>,
+    topdown: bool,
+    onerror: Option<PyObject>,
+    filter: Option<Arc<Filter>>,
+    top_dev: Option<u64>,
+    now: f64,
+    is_bytes: bool,
+}
+
+impl Walk {
+    pub fn new(
+        top: OsString,
+        topdown: bool,
+        onerror: Option<PyObject>,
+        followlinks: bool,
+        ignore: Option<Arc<Ignore>>,
+        filter: Option<Arc<Filter>>,
+        is_bytes: bool,
+    ) -> Self {
+        // the pool can't tell how deep a directory is, so only the
+        // directories a filter selects are read ahead
+        let pool = Pool::new(followlinks, !topdown && filter.is_none(), followlinks, ignore);
+        pool.request([top.clone()]);
+        let top_dev = match filter {
+            Some(ref f) if f.xdev => std::fs::metadata(&top).ok().map(|m| m.dev()),
+            _ => None,
+        };
+        Walk {
+            pool,
+            stack: vec![Frame::Visit(top, None, 0)],
+            pending: None,
+            topdown,
+            onerror,
+            filter,
+            top_dev,
+            now: finder::now(),
+            is_bytes,
+        }
+    }
+
+    fn report(&self, py: Python, e: &io::Error, path: &OsStr) -> PyResult<()> {
+        if let Some(ref onerror) = self.onerror {
+            onerror.call1(py, (oserror(e, path).value(py),))?;
+        }
+        Ok(())
+    }
+
+    fn process_pending(&mut self, py: Python) -> PyResult<()> {
+        let pending = match self.pending.take() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let followlinks = self.pool.followlinks();
+        let mut walk_into = vec![];
+        for name in pending.dirnames.as_ref(py).iter() {
+            let name = py2os(name)?;
+            let is_symlink = match pending.symlinks.get(&name) {
+                Some(v) => *v,
+                None => std::fs::symlink_metadata(join_name(&pending.dirpath, &name))
+                    .map(|m| m.file_type().is_symlink())
+                    .unwrap_or(false),
+            };
+            let allowed = match pending.filtered {
+                Some((ref descend, _)) => descend.contains(&name),
+                None => followlinks || !is_symlink,
+            };
+            if allowed {
+                walk_into.push(join_name(&pending.dirpath, &name));
+            }
+        }
+        if let Some((_, unlisted)) = pending.filtered {
+            walk_into.extend(unlisted);
+        }
+        let wanted: HashSet<&OsString> = walk_into.iter().collect();
+        for p in pending.prefetched.iter() {
+            if !wanted.contains(p) {
+                self.pool.cancel(p);
+            }
+        }
+        for p in walk_into.iter().rev() {
+            self.stack.push(Frame::Visit(p.clone(), pending.ancestors.clone(), pending.depth + 1));
+        }
+        Ok(())
+    }
+
+    fn next_entry(&mut self, py: Python) -> PyResult<Option<PyObject>> {
+        self.process_pending(py)?;
+        let followlinks = self.pool.followlinks();
+        loop {
+            let (path, ancestors, depth) = match self.stack.pop() {
+                Some(Frame::Visit(p, a, d)) => (p, a, d),
+                Some(Frame::Yield(p, dirnames, filenames)) => {
+                    let is_bytes = self.is_bytes;
+                    let py_dirnames = PyList::new(py, dirnames.iter().map(|x| os2py(py, x, is_bytes)));
+                    let py_filenames = PyList::new(py, filenames.iter().map(|x| os2py(py, x, is_bytes)));
+                    let ret = PyTuple::new(py, &[os2py(py, &p, is_bytes), py_dirnames.to_object(py), py_filenames.to_object(py)]);
+                    return Ok(Some(ret.to_object(py)));
+                }
+                None => return Ok(None),
+            };
+            let pool = self.pool.clone();
+            let filter = self.filter.clone();
+            let (top_dev, now) = (self.top_dev, self.now);
+            let result = {
+                let path = path.clone();
+                py.allow_threads(move || {
+                    pool.take(&path).map(|scan| {
+                        let id = scan.id;
+                        let selected = match filter {
+                            Some(f) => f.select(&path, scan, depth + 1, followlinks, top_dev, now),
+                            None => Selected::all(scan, followlinks),
+                        };
+                        (id, selected)
+                    })
+                })
+            };
+            let (id, selected) = match result {
+                Ok(s) => s,
+                Err(e) => {
+                    self.report(py, &e, &path)?;
+                    continue;
+                }
+            };
+            for (e, p) in selected.errors.iter() {
+                self.report(py, e, p)?;
+            }
+            let ancestors = match (followlinks, id) {
+                (true, Some(id)) => {
+                    if Ancestor::contains(&ancestors, id) {
+                        continue;
+                    }
+                    Ancestor::push(ancestors, id)
+                }
+                _ => ancestors,
+            };
+            let children: Vec<OsString> = selected.descend.iter().map(|name| join_name(&path, name)).collect();
+            self.pool.request(children.iter().cloned());
+            let dirnames: Vec<OsString> = selected.dirs.iter().map(|(name, _)| name.clone()).collect();
+
+            if !self.topdown {
+                self.stack.push(Frame::Yield(path, dirnames, selected.files));
+                for child in children.into_iter().rev() {
+                    self.stack.push(Frame::Visit(child, ancestors.clone(), depth + 1));
+                }
+                continue;
+            }
+
+            let filtered = self.filter.as_ref().map(|_| {
+                let listed: HashSet<&OsString> = dirnames.iter().collect();
+                let unlisted = selected.descend.iter().filter(|x| !listed.contains(x)).map(|x| join_name(&path, x)).collect();
+                (selected.descend.iter().cloned().collect(), unlisted)
+            });
+            let is_bytes = self.is_bytes;
+            let py_dirnames = PyList::new(py, dirnames.iter().map(|x| os2py(py, x, is_bytes)));
+            let py_filenames = PyList::new(py, selected.files.iter().map(|x| os2py(py, x, is_bytes)));
+            let ret = PyTuple::new(py, &[os2py(py, &path, is_bytes), py_dirnames.to_object(py), py_filenames.to_object(py)]);
+            self.pending = Some(Pending {
+                dirpath: path,
+                dirnames: py_dirnames.into(),
+                symlinks: selected.dirs.into_iter().collect(),
+                prefetched: children,
+                ancestors,
+                depth,
+                filtered,
+            });
+            return Ok(Some(ret.to_object(py)));
+        }
+    }
+}
+
+#[pymethods]
+impl Walk {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
+        let py = slf.py();
+        slf.next_entry(py)
+    }
+}
+
+impl Drop for Walk {
+    fn drop(&mut self) {
+        self.pool.shutdown();
+    }
+}
@@ -0,0 +1,1241 @@
+"""
+tests for fpath specific functions (not in os.path)
+"""
+import errno
+import fnmatch
+import glob
+import os
+import pwd
+import re
+import shutil
+import sys
+import tempfile
+import time
+import unittest
+
+import fpath
+import fpath.fnmatch
+import fpath.pathlist
+
+
+def make_tree(root, paths):
+    for p in paths:
+        full = os.path.join(root, p)
+        if p.endswith("/"):
+            os.makedirs(full, exist_ok=True)
+        else:
+            os.makedirs(os.path.dirname(full), exist_ok=True)
+            with open(full, "w") as f:
+                f.write(p)
+
+
+def sorted_walk(it):
+    return sorted((d, sorted(dn), sorted(fn)) for d, dn, fn in it)
+
+
+class TreeTestCase(unittest.TestCase):
+    """A fresh temporary directory per test, laid out by `make_tree(tree)`.
+    `root` is its realpath, so results compare equal as strings."""
+
+    tree = []
+
+    def setUp(self):
+        self.tmp = tempfile.TemporaryDirectory()
+        self.addCleanup(self.tmp.cleanup)
+        self.root = os.path.realpath(self.tmp.name)
+        make_tree(self.root, self.tree)
+
+    def path(self, *parts):
+        return os.path.join(self.root, *parts)
+
+
+class RelativeToTest(unittest.TestCase):
+
+    def test_relative_to(self):
+        self.assertEqual(fpath.relative_to("/a/b/c", "/a"), "b/c")
+        self.assertEqual(fpath.relative_to("/a/b", "/a/b"), ".")
+        self.assertEqual(fpath.relative_to(b"/a/b/c", b"/a/"), b"b/c")
+        self.assertRaises(ValueError, fpath.relative_to, "/a/b", "/c")
+        self.assertRaises(ValueError, fpath.relative_to, "/a/b", "a")
+        self.assertRaises(TypeError, fpath.relative_to, "/a/b", b"/a")
+
+    def test_relative_to_walk_up(self):
+        self.assertEqual(fpath.relative_to("/a/b", "/a/c", walk_up=True), "../b")
+        self.assertEqual(fpath.relative_to(b"a", b"a/b/c", walk_up=True), b"../..")
+        self.assertRaises(ValueError, fpath.relative_to, "/a/b", "/a/../c", walk_up=True)
+        self.assertRaises(ValueError, fpath.relative_to, "/a/b", "a", walk_up=True)
+
+    def test_is_relative_to(self):
+        self.assertTrue(fpath.is_relative_to("/a/b", "/a"))
+        self.assertTrue(fpath.is_relative_to(b"a/./b", b"a"))
+        self.assertFalse(fpath.is_relative_to("/ab", "/a"))
+        self.assertFalse(fpath.is_relative_to("a/b", "/a"))
+
+
+class SplitextAllTest(unittest.TestCase):
+
+    def test_splitext_all(self):
+        self.assertEqual(fpath.splitext_all("dist/archive.tar.gz"), ("dist/archive", [".tar", ".gz"]))
+        self.assertEqual(fpath.splitext_all(b"/a/b.d.ts"), (b"/a/b", [b".d", b".ts"]))
+        self.assertEqual(fpath.splitext_all(".bashrc"), (".bashrc", []))
+        self.assertEqual(fpath.splitext_all("file."), ("file.", []))
+
+    def test_splitext_known(self):
+        self.assertEqual(fpath.splitext_known("archive.tar.gz"), ("archive", ".tar.gz"))
+        self.assertEqual(fpath.splitext_known(b"app.min.js"), (b"app", b".min.js"))
+        self.assertEqual(fpath.splitext_known("file.txt"), ("file", ".txt"))
+        self.assertEqual(fpath.splitext_known("archive.tar.gz", known=[]), ("archive.tar", ".gz"))
+        self.assertEqual(fpath.splitext_known("a.pkg.tar.zst", known=["pkg.tar.zst"]), ("a", ".pkg.tar.zst"))
+        self.assertRaises(TypeError, fpath.splitext_known, "a.tar.gz", [b".tar.gz"])
+        self.assertIn(".tar.gz", fpath.COMPOUND_EXTENSIONS)
+
+
+class ComponentsTest(unittest.TestCase):
+
+    def test_components(self):
+        self.assertEqual(list(fpath.components("/usr//lib/./python3")), ["/", "usr", "lib", "python3"])
+        self.assertEqual(list(fpath.components(b"a/../b/c")), [b"b", b"c"])
+        self.assertEqual(list(fpath.components(".")), [])
+
+    def test_ancestors(self):
+        self.assertEqual(list(fpath.ancestors("/usr/lib/python3")), ["/usr/lib", "/usr", "/"])
+        self.assertEqual(list(fpath.ancestors(b"a/b")), [b"a", b"."])
+        self.assertEqual(list(fpath.ancestors("//a")), ["//"])
+        self.assertEqual(list(fpath.ancestors("/")), [])
+
+    def test_ancestors_matches_dirname(self):
+        p = "/home/user/path/to/file.txt"
+        parents = []
+        while p != fpath.dirname(p):
+            p = fpath.dirname(p)
+            parents.append(p)
+        self.assertEqual(list(fpath.ancestors("/home/user/path/to/file.txt")), parents)
+
+
+class SplitallTest(unittest.TestCase):
+
+    def test_splitall(self):
+        self.assertEqual(fpath.splitall("/usr//lib/../bin/"), ("/", ["usr", "bin"]))
+        self.assertEqual(fpath.splitall(b"//srv/data"), (b"//", [b"srv", b"data"]))
+        self.assertEqual(fpath.splitall("a/./b"), ("", ["a", "b"]))
+
+    def test_joinall(self):
+        self.assertEqual(fpath.joinall(["usr", "bin"], "/"), "/usr/bin")
+        self.assertEqual(fpath.joinall([b"a", b"b"]), b"a/b")
+        self.assertEqual(fpath.joinall([], b"//"), b"//")
+        self.assertEqual(fpath.joinall(["a", "/b", "c"]), fpath.join("a", "/b", "c"))
+        self.assertRaises(TypeError, fpath.joinall, ["a", b"b"])
+        self.assertRaises(TypeError, fpath.joinall, [b"a"], "/")
+
+    def test_roundtrip(self):
+        for p in ("/a/b/../c", "//a//b", "a/b/", "", ".", "/", b"/x/./y"):
+            anchor, parts = fpath.splitall(p)
+            self.assertEqual(fpath.joinall(parts, anchor), fpath.normpath(p))
+
+
+class MatchTest(unittest.TestCase):
+
+    def test_match(self):
+        self.assertTrue(fpath.match("/a/b/c.py", "*.py"))
+        self.assertTrue(fpath.match("/a/b/c.py", "b/*.py"))
+        self.assertFalse(fpath.match("/a/b/c.py", "a/*.py"))
+        self.assertTrue(fpath.match("/a/b/c.py", "/a/**/*.py"))
+        self.assertFalse(fpath.match("/a/b/c.py", "/*.py"))
+        self.assertTrue(fpath.match(b"a/B.PY", b"*.py", case_sensitive=False))
+        self.assertFalse(fpath.match("a/B.PY", "*.py"))
+        self.assertRaises(ValueError, fpath.match, "a", "")
+        self.assertRaises(ValueError, fpath.match, "a", ".")
+        self.assertRaises(ValueError, fpath.match, "a", "./")
+        self.assertRaises(ValueError, fpath.full_match, "a", "./")
+        self.assertFalse(fpath.match("a", "/"))
+        self.assertRaises(TypeError, fpath.match, "a", b"a")
+
+    def test_full_match(self):
+        self.assertTrue(fpath.full_match("src/a/b/lib.rs", "src/**/*.rs"))
+        self.assertTrue(fpath.full_match("src/lib.rs", "src/**/*.rs"))
+        self.assertFalse(fpath.full_match("lib/src/lib.rs", "src/**/*.rs"))
+        self.assertTrue(fpath.full_match("/x/y.py", "**/*.py"))
+        self.assertTrue(fpath.full_match("a/b", "a/b/**"))
+        self.assertFalse(fpath.full_match("a/b/c.py", "*.py"))
+        self.assertTrue(fpath.full_match("a/c/d", "a/[!a]/?"))
+
+
+class WalkTest(TreeTestCase):
+
+    tree = ["a/b/c/f1", "a/f2", "d/", "e/node_modules/x/f3", "f4"]
+
+    def setUp(self):
+        super().setUp()
+        os.symlink("a", self.path("link"))
+
+    def test_walk(self):
+        for topdown in (True, False):
+            self.assertEqual(sorted_walk(fpath.walk(self.root, topdown=topdown)),
+                             sorted_walk(os.walk(self.root, topdown=topdown)))
+        broot = os.fsencode(self.root)
+        self.assertEqual(sorted_walk(fpath.walk(broot)), sorted_walk(os.walk(broot)))
+
+    def test_walk_order(self):
+        seen = [d for d, _, _ in fpath.walk(self.root, topdown=False)]
+        self.assertEqual(seen[-1], self.root)
+        self.assertLess(seen.index(self.path("a", "b")), seen.index(self.path("a")))
+        seen = [d for d, _, _ in fpath.walk(self.root)]
+        self.assertEqual(seen[0], self.root)
+
+    def test_walk_prune(self):
+        seen = []
+        for dirpath, dirnames, filenames in fpath.walk(self.root):
+            if "node_modules" in dirnames:
+                dirnames.remove("node_modules")
+            seen.append(dirpath)
+        self.assertIn(self.path("e"), seen)
+        self.assertNotIn(self.path("e", "node_modules"), seen)
+
+    def test_walk_onerror(self):
+        errors = []
+        self.assertEqual(list(fpath.walk(self.path("missing"), onerror=errors.append)), [])
+        self.assertIsInstance(errors[0], FileNotFoundError)
+        self.assertEqual(errors[0].filename, self.path("missing"))
+
+    def test_walk_followlinks(self):
+        self.assertEqual(sorted_walk(fpath.walk(self.root, followlinks=True)),
+                         sorted_walk(os.walk(self.root, followlinks=True)))
+        os.symlink("..", self.path("a", "b", "loop"))
+        seen = [d for d, _, _ in fpath.walk(self.root, followlinks=True)]
+        self.assertNotIn(self.path("a", "b", "loop"), seen)
+        self.assertEqual(len(seen), len(set(seen)))
+
+
+class GlobTest(TreeTestCase):
+
+    tree = ["a/b/c/f.py", "a/f.txt", ".hid/x/y.py", "a/.h/z.py", "top.py", ".dot.py", "d/"]
+
+    def setUp(self):
+        super().setUp()
+        os.symlink("a", self.path("link"))
+        os.symlink("missing", self.path("broken"))
+
+    def assertSameGlob(self, pattern, **kwargs):
+        expected = sorted(glob.glob(pattern, **kwargs))
+        self.assertEqual(sorted(fpath.glob(pattern, **kwargs)), expected)
+        self.assertEqual(sorted(fpath.iglob(pattern, **kwargs)), expected)
+
+    def test_glob(self):
+        for pattern in ("*", "*/", ".*", "[ab]*", "[!a]*", "a/*/c", "broken", "missing", "a/f.txt", "a/", "*/*/"):
+            self.assertSameGlob(self.path(pattern))
+            self.assertSameGlob(pattern, root_dir=self.root)
+            self.assertSameGlob(os.fsencode(pattern), root_dir=os.fsencode(self.root))
+        self.assertRaises(TypeError, fpath.glob, b"*", root_dir=self.root)
+        self.assertRaises(TypeError, fpath.iglob, "*", root_dir=os.fsencode(self.root))
+
+    def test_glob_recursive(self):
+        for pattern in ("**", "**/", "**/*.py", "a/**", "link/**", "**/.*", "a/**/**/*.py"):
+            self.assertSameGlob(pattern, root_dir=self.root, recursive=True)
+            if sys.version_info >= (3, 11):
+                self.assertSameGlob(pattern, root_dir=self.root, recursive=True, include_hidden=True)
+            self.assertSameGlob(self.path(pattern), recursive=True)
+
+    def test_glob_dir_fd(self):
+        fd = os.open(self.path("a"), os.O_RDONLY)
+        try:
+            for pattern in ("*", "**/*.py", "b/*", "../*", "b/"):
+                self.assertSameGlob(pattern, dir_fd=fd, recursive=True)
+        finally:
+            os.close(fd)
+
+    def test_iglob_is_lazy(self):
+        it = fpath.iglob("**", root_dir=self.root, recursive=True)
+        self.assertIn(next(it), sorted(glob.glob("**", root_dir=self.root, recursive=True)))
+
+
+class FnmatchTest(unittest.TestCase):
+
+    patterns = ["*", "*.py", "a*b*c", "?", "[a-c]", "[!a-c]x", "[z-a]", "[c-b-a]", "[]]", "[!]]", "[abc",
+                "[a&&b]", "[^a]", "\\*", "(a|b)", "*[!.]*"]
+    names = ["", "a", "b.py", "abc", "axbxc", "]", "^", "&", "-", "x.y", "a\nb", "\\*", "(a|b)", ".x"]
+
+    def test_translate(self):
+        # before 3.11 the group names come from a counter shared with the
+        # stdlib, so only their numbering differs
+        groups = re.compile(r"\(\?P[<=]g\d+")
+        for pat in self.patterns:
+            ret = fpath.fnmatch.translate(pat)
+            self.assertEqual(groups.sub("", ret), groups.sub("", fnmatch.translate(pat)))
+            re.compile(ret)
+        joined = "|".join(fpath.fnmatch.translate("a*b*c") for _ in range(2)) + "|" + fnmatch.translate("a*b*c")
+        self.assertTrue(re.match(joined, "abc"))
+
+    def test_fnmatch(self):
+        for pat in self.patterns:
+            for name in self.names:
+                expected = fnmatch.fnmatch(name, pat)
+                self.assertEqual(fpath.fnmatch.fnmatch(name, pat), expected, (name, pat))
+                self.assertEqual(fpath.fnmatch.fnmatchcase(os.fsencode(name), os.fsencode(pat)), expected, (name, pat))
+
+    def test_filter(self):
+        for pat in self.patterns:
+            self.assertEqual(fpath.fnmatch.filter(self.names, pat), fnmatch.filter(self.names, pat))
+        self.assertEqual(fpath.fnmatch.filter([b"a.c", b"b.h"], b"*.c"), [b"a.c"])
+
+    def test_types(self):
+        self.assertTrue(fpath.fnmatch.fnmatch(b"\xe9", b"?"))
+        self.assertRaises(TypeError, fpath.fnmatch.fnmatch, "a", b"a")
+        self.assertRaises(TypeError, fpath.fnmatch.fnmatchcase, b"a", "a")
+        self.assertRaises(TypeError, fpath.fnmatch.filter, [b"a"], "a")
+
+
+
+class GlobSetTest(unittest.TestCase):
+
+    patterns = ["**/*.rs", "src/lib.rs", "src/*", "docs/**", "*.md", "**/test_*.py"]
+    paths = ["src/lib.rs", "src/a/b.rs", "docs/a/README.md", "README.md",
+             "tests/test_fpath.py", "/abs/x.rs", "setup.py", "src"]
+
+    def test_matches(self):
+        gs = fpath.GlobSet(self.patterns)
+        self.assertEqual(len(gs), len(self.patterns))
+        self.assertEqual(gs.matches("src/lib.rs"), [0, 1, 2])
+        self.assertEqual(gs.first("src/lib.rs"), 0)
+        self.assertEqual(gs.last("src/lib.rs"), 2)
+        self.assertIsNone(gs.first("setup.py"))
+        self.assertFalse(gs.is_match("setup.py"))
+        self.assertTrue(gs.is_match(b"README.md"))
+        for path in self.paths:
+            expected = [i for i, p in enumerate(self.patterns) if fpath.full_match(path, p)]
+            self.assertEqual(gs.matches(path), expected, path)
+
+    def test_many(self):
+        gs = fpath.GlobSet(self.patterns)
+        expected = [gs.matches(x) for x in self.paths]
+        self.assertEqual(gs.matches_many(self.paths), expected)
+        self.assertEqual(gs.first_many(self.paths), [x[0] if x else None for x in expected])
+        self.assertEqual(gs.last_many(self.paths), [x[-1] if x else None for x in expected])
+
+    def test_options(self):
+        gs = fpath.GlobSet(["*.MD", "src/*"], case_sensitive=False)
+        self.assertEqual(gs.matches("readme.md"), [0])
+        self.assertEqual(gs.matches("docs/readme.md"), [])
+        gs = fpath.GlobSet(["*.md", "src/*"], literal_separator=False)
+        self.assertEqual(gs.matches("docs/readme.md"), [0])
+        self.assertEqual(gs.matches("src/a/b"), [1])
+        self.assertRaises(ValueError, fpath.GlobSet, [""])
+
+
+class IgnoreTest(TreeTestCase):
+
+    tree = [
+        "keep.py", "x.log", "important.log", "build/out.o", "src/build/gen.c", "src/a.o",
+        "src/build.txt", "docs/a.md", "docs/tmp/b.md", "sub/x.log", "sub/y.log", "sub/deep/z.tmp",
+        "vendor/lib/keep.c",
+    ]
+
+    def setUp(self):
+        super().setUp()
+        self.write(".gitignore", "# comment\n*.log\n!important.log\n/build/\nbuild/\n*.o\ndocs/**/*.md\nvendor/\n!vendor/lib/\n")
+        self.write("sub/.gitignore", "!x.log\n*.tmp\n")
+        self.write("sub/.ignore", "x.log\n")
+        self.write("docs/.gitignore", "!tmp/b.md\n")
+
+    def write(self, name, text):
+        with open(self.path(name), "w") as f:
+            f.write(text)
+
+    def ignored(self, matcher, paths):
+        return sorted(p for p in paths if matcher.is_ignored(self.path(p)))
+
+    def test_is_ignored(self):
+        m = fpath.IgnoreMatcher(self.root)
+        self.assertEqual(m.root, self.root)
+        paths = ["keep.py", "x.log", "important.log", "build", "build/out.o", "src/build", "src/build/gen.c",
+                 "src/a.o", "src/build.txt", "docs/a.md", "docs/tmp/b.md", "sub/x.log", "sub/y.log",
+                 "sub/deep/z.tmp", "vendor/lib/keep.c"]
+        self.assertEqual(self.ignored(m, paths), [
+            "build", "build/out.o", "docs/a.md", "src/a.o", "src/build", "src/build/gen.c",
+            "sub/deep/z.tmp", "sub/x.log", "sub/y.log", "vendor/lib/keep.c", "x.log",
+        ])
+        self.assertTrue(m.is_ignored(self.path("other/build"), is_dir=True))
+        self.assertFalse(m.is_ignored(self.path("other/build"), is_dir=False))
+        self.assertFalse(m.is_ignored("/elsewhere/x.log"))
+
+    def test_patterns_and_filenames(self):
+        m = fpath.IgnoreMatcher(self.root, patterns=["*.py", "!x.log"], filenames=[".ignore"])
+        self.assertEqual(self.ignored(m, ["keep.py", "x.log", "sub/x.log", "build/out.o"]), ["keep.py", "sub/x.log"])
+
+    def test_walk(self):
+        m = fpath.IgnoreMatcher(self.root)
+        for topdown in (True, False):
+            found = sorted(os.path.relpath(os.path.join(d, f), self.root)
+                           for d, _, fs in fpath.walk(self.root, topdown=topdown, ignore=m) for f in fs)
+            self.assertEqual(found, [
+                ".gitignore", "docs/.gitignore", "docs/tmp/b.md", "important.log", "keep.py",
+                "src/build.txt", "sub/.gitignore", "sub/.ignore",
+            ])
+
+    def test_glob(self):
+        m = fpath.IgnoreMatcher(self.root)
+        self.assertEqual(sorted(fpath.glob("**/*.*", root_dir=self.root, recursive=True, ignore=m)), [
+            "docs/tmp/b.md", "important.log", "keep.py", "src/build.txt",
+        ])
+        self.assertEqual(fpath.glob(self.path("build/*"), ignore=m), [])
+        self.assertEqual(list(fpath.iglob("x.log", root_dir=self.root, ignore=m)), [])
+        self.assertRaises(ValueError, fpath.glob, "*", dir_fd=0, ignore=m)
+
+
+class FindTest(TreeTestCase):
+
+    tree = ["a/b/c/f.py", "a/f.txt", "a/g.py", "top.py", "d/"]
+
+    def setUp(self):
+        super().setUp()
+        with open(self.path("a/big.bin"), "wb") as f:
+            f.write(b"x" * 3000)
+        os.symlink("a", self.path("link"))
+        old = time.time() - 10 * 86400
+        os.utime(self.path("a/g.py"), (old, old))
+
+    def find(self, **kwargs):
+        return sorted(os.path.relpath(p, self.root) for p in fpath.find(self.root, **kwargs))
+
+    def test_find(self):
+        everything = [".", "a", "a/b", "a/b/c", "a/b/c/f.py", "a/big.bin", "a/f.txt", "a/g.py", "d", "link", "top.py"]
+        self.assertEqual(self.find(), everything)
+        self.assertEqual(self.find(type="d"), [".", "a", "a/b", "a/b/c", "d"])
+        self.assertEqual(self.find(type="l"), ["link"])
+        self.assertEqual(self.find(type="f", name="*.py"), ["a/b/c/f.py", "a/g.py", "top.py"])
+        self.assertEqual(self.find(type="f", size=">2k"), ["a/big.bin"])
+        self.assertEqual(self.find(type="f", size="<2k", name="f.*"), ["a/b/c/f.py", "a/f.txt"])
+        # like find(1), sizes are rounded up to whole units, 512-byte
+        # blocks by default
+        self.assertEqual(self.find(type="f", size="<1k"), [])
+        self.assertEqual(self.find(type="f", size="6"), ["a/big.bin"])
+        self.assertEqual(self.find(type="f", size="3000c"), ["a/big.bin"])
+        self.assertEqual(self.find(mtime="+7", type="f"), ["a/g.py"])
+        self.assertEqual(self.find(mtime="<1", name="*.py"), ["a/b/c/f.py", "top.py"])
+        self.assertEqual(self.find(newer=self.path("a/g.py"), name="*.py"), ["a/b/c/f.py", "top.py"])
+        # ages are rounded down to whole units too: 7.5 days is 7
+        old = time.time() - 7.5 * 86400
+        os.utime(self.path("top.py"), (old, old))
+        self.assertEqual(self.find(mtime="+7", name="top.py"), [])
+        self.assertEqual(self.find(mtime="7", name="top.py"), ["top.py"])
+        self.assertEqual(self.find(mtime="-8", name="top.py"), ["top.py"])
+        self.assertEqual(self.find(mtime="-7", name="top.py"), [])
+
+    def test_depth_and_links(self):
+        self.assertEqual(self.find(maxdepth=1), [".", "a", "d", "link", "top.py"])
+        self.assertEqual(self.find(mindepth=3), ["a/b/c", "a/b/c/f.py"])
+        self.assertEqual(self.find(xdev=True, type="d", maxdepth=1), [".", "a", "d"])
+        self.assertIn("link/b/c/f.py", self.find(followlinks=True))
+        self.assertEqual(self.find(followlinks=True, type="l"), [])
+        self.assertIsInstance(next(fpath.find(os.fsencode(self.root))), bytes)
+
+    def test_owner(self):
+        st = os.stat(self.root)
+        self.assertEqual(self.find(user=st.st_uid, group=st.st_gid, maxdepth=0), ["."])
+        self.assertEqual(self.find(user=pwd.getpwuid(st.st_uid).pw_name, maxdepth=0), ["."])
+        self.assertEqual(self.find(user=st.st_uid + 1, maxdepth=0), [])
+        self.assertRaises(ValueError, fpath.find, self.root, user="no such user")
+        self.assertRaises(ValueError, fpath.find, self.root, type="x")
+        self.assertRaises(ValueError, fpath.find, self.root, size=">1Q")
+        self.assertRaises(FileNotFoundError, fpath.find, self.root, newer=self.path("missing"))
+
+    def test_onerror(self):
+        errors = []
+        self.assertEqual(list(fpath.find(self.path("missing"), onerror=errors.append)), [])
+        self.assertIsInstance(errors[0], FileNotFoundError)
+        self.assertEqual(list(fpath.find(self.path("missing"))), [])
+        if os.geteuid() != 0:
+            os.chmod(self.path("a/b"), 0)
+            try:
+                errors = []
+                found = self.find(onerror=errors.append)
+                self.assertIn("a/b", found)
+                self.assertNotIn("a/b/c", found)
+                self.assertEqual([e.filename for e in errors], [self.path("a/b")])
+                self.assertIsInstance(errors[0], PermissionError)
+            finally:
+                os.chmod(self.path("a/b"), 0o755)
+
+    def test_walk_filter(self):
+        def walk(**kwargs):
+            ret = []
+            for dirpath, dirnames, filenames in fpath.walk(self.root, **kwargs):
+                rel = os.path.relpath(dirpath, self.root)
+                ret.extend(os.path.normpath(os.path.join(rel, x)) for x in dirnames + filenames)
+            return sorted(ret)
+
+        for kwargs in ({"type": "f", "name": "*.py"}, {"size": ">2k"}, {"type": "d"}, {"mindepth": 2, "maxdepth": 3},
+                       {"mtime": "+7"}, {"type": "l"}, {"xdev": True, "maxdepth": 1}):
+            expected = [x for x in self.find(**kwargs) if x != "."]
+            self.assertEqual(walk(filter=fpath.FindFilter(**kwargs)), expected, kwargs)
+            self.assertEqual(walk(filter=fpath.FindFilter(**kwargs), topdown=False), expected, kwargs)
+
+        # the directories listed can still be pruned, unlisted ones are walked
+        ret = []
+        for dirpath, dirnames, filenames in fpath.walk(self.root, filter=fpath.FindFilter(name="[ab]")):
+            ret.append((os.path.relpath(dirpath, self.root), sorted(dirnames), filenames))
+            if "b" in dirnames:
+                dirnames.remove("b")
+        self.assertEqual(sorted(ret), [(".", ["a"], []), ("a", ["b"], []), ("d", [], [])])
+        ret = [(os.path.relpath(d, self.root), dirnames) for d, dirnames, _ in fpath.walk(self.root, filter=fpath.FindFilter(type="f"))]
+        self.assertIn(("a/b/c", []), ret)
+        self.assertEqual([x for x in ret if x[1]], [])
+
+    def test_scandir_filter(self):
+        names = lambda **kwargs: sorted(e.name for e in fpath.scandir(self.path("a"), filter=fpath.FindFilter(**kwargs)))
+        self.assertEqual(names(type="f", name="*.py"), ["g.py"])
+        self.assertEqual(names(type="d"), ["b"])
+        self.assertEqual(names(type="f", size="+2k"), ["big.bin"])
+        self.assertEqual(names(mindepth=2), [])
+        self.assertEqual(names(maxdepth=0), [])
+        self.assertEqual(names(), ["b", "big.bin", "f.txt", "g.py"])
+
+
+class ScandirTest(TreeTestCase):
+
+    tree = ["file", "dir/"]
+
+    def setUp(self):
+        super().setUp()
+        os.symlink("dir", self.path("link"))
+        os.symlink("missing", self.path("broken"))
+        os.mkfifo(self.path("fifo"))
+
+    def test_listdir_typed(self):
+        expected = [("broken", "symlink"), ("dir", "dir"), ("fifo", "other"), ("file", "file"), ("link", "symlink")]
+        self.assertEqual(sorted(fpath.listdir_typed(self.root)), expected)
+        self.assertEqual(sorted(fpath.listdir_typed(os.fsencode(self.root))),
+                         [(os.fsencode(n), t) for n, t in expected])
+        self.assertRaises(FileNotFoundError, fpath.listdir_typed, self.path("missing"))
+        self.assertRaises(NotADirectoryError, fpath.listdir_typed, self.path("file"))
+
+    def test_scandir(self):
+        entries = {e.name: e for e in fpath.scandir(self.root)}
+        for e in os.scandir(self.root):
+            entry = entries[e.name]
+            self.assertEqual(entry.path, e.path)
+            self.assertEqual(os.fspath(entry), e.path)
+            self.assertEqual(entry.inode(), e.inode())
+            self.assertEqual(entry.is_symlink(), e.is_symlink())
+            for follow in (True, False):
+                self.assertEqual(entry.is_dir(follow_symlinks=follow), e.is_dir(follow_symlinks=follow), e.name)
+                self.assertEqual(entry.is_file(follow_symlinks=follow), e.is_file(follow_symlinks=follow), e.name)
+        self.assertEqual(entries["link"].stat().st_ino, os.stat(self.path("dir")).st_ino)
+        self.assertEqual(entries["link"].stat(follow_symlinks=False).st_ino, os.lstat(self.path("link")).st_ino)
+        self.assertIs(entries["file"].stat(), entries["file"].stat(follow_symlinks=False))
+        self.assertRaises(FileNotFoundError, entries["broken"].stat)
+        self.assertEqual(entries["fifo"].d_type, "other")
+        self.assertEqual(repr(entries["file"]), "<DirEntry 'file'>")
+        entry = fpath.scandir(os.fsencode(self.root))[0]
+        self.assertIsInstance(entry.path, bytes)
+
+
+class DiskUsageTest(TreeTestCase):
+
+    tree = ["a/b/", "c/"]
+
+    def setUp(self):
+        super().setUp()
+        for name, size in (("a/x", 5000), ("a/b/y", 100), ("z", 10)):
+            with open(self.path(name), "wb") as f:
+                f.write(b"x" * size)
+        os.link(self.path("a/x"), self.path("c/x"))
+        os.symlink("a", self.path("link"))
+
+    def apparent_total(self, follow_symlinks=False):
+        seen, total = set(), 0
+        for d, dirs, files in os.walk(self.root, followlinks=follow_symlinks):
+            for p in [d] + [os.path.join(d, n) for n in files + dirs]:
+                st = os.stat(p, follow_symlinks=follow_symlinks)
+                if (st.st_dev, st.st_ino) not in seen:
+                    seen.add((st.st_dev, st.st_ino))
+                    total += st.st_size
+        return total
+
+    def test_apparent(self):
+        sizes = fpath.disk_usage_tree(self.root, apparent=True)
+        dir_size = os.stat(self.root).st_size
+        self.assertEqual(sorted(sizes), sorted([self.root] + [self.path(d) for d in ("a", "a/b", "c")]))
+        self.assertEqual(sizes[self.path("a/b")], dir_size + 100)
+        self.assertEqual(sizes[self.path("a")] + sizes[self.path("c")],
+                         3 * dir_size + 5100)
+        self.assertEqual(sizes[self.root], self.apparent_total())
+        self.assertEqual(fpath.disk_usage_tree(self.root, apparent=True, follow_symlinks=True)[self.root],
+                         self.apparent_total(follow_symlinks=True))
+
+    def test_blocks(self):
+        root = os.fsencode(self.root)
+        sizes = fpath.disk_usage_tree(root, max_depth=1)
+        self.assertEqual(sorted(sizes), [root, root + b"/a", root + b"/c"])
+        self.assertEqual(sizes[root + b"/a"] % 512, 0)
+        x_blocks = os.stat(self.path("a/x")).st_blocks * 512
+        self.assertGreaterEqual(sizes[root], x_blocks)
+        self.assertEqual(fpath.disk_usage_tree(self.path("z"), apparent=True), {self.path("z"): 10})
+        self.assertRaises(FileNotFoundError, fpath.disk_usage_tree, self.path("missing"))
+
+    @unittest.skipIf(os.geteuid() == 0, "root ignores permissions")
+    def test_onerror(self):
+        errors = []
+        fpath.disk_usage_tree(self.root, onerror=errors.append)
+        self.assertEqual(errors, [])
+        os.chmod(self.path("a/b"), 0)
+        try:
+            sizes = fpath.disk_usage_tree(self.root, apparent=True, onerror=errors.append)
+        finally:
+            os.chmod(self.path("a/b"), 0o755)
+        self.assertEqual(len(errors), 1)
+        self.assertIsInstance(errors[0], PermissionError)
+        self.assertEqual(errors[0].filename, self.path("a/b"))
+        self.assertEqual(sizes[self.path("a/b")], os.stat(self.path("a/b")).st_size)
+
+
+class WhichTest(TreeTestCase):
+
+    tree = ["bin1/tool", "bin1/data", "bin2/tool", "bin2/sub/"]
+
+    def setUp(self):
+        super().setUp()
+        for name in ("bin1/tool", "bin2/tool"):
+            os.chmod(self.path(name), 0o755)
+        self.bin1 = self.path("bin1")
+        self.bin2 = self.path("bin2")
+        self.search_path = os.pathsep.join([self.bin1, self.bin2, self.bin1])
+
+    def test_which(self):
+        for cmd in ("tool", "data", "sub", "missing", os.path.join(self.bin2, "tool")):
+            for mode in (os.F_OK | os.X_OK, os.R_OK):
+                self.assertEqual(fpath.which(cmd, mode, self.search_path), shutil.which(cmd, mode, self.search_path), (cmd, mode))
+        self.assertEqual(fpath.which(b"tool", path=os.fsencode(self.search_path)), os.fsencode(os.path.join(self.bin1, "tool")))
+        self.assertIsNone(fpath.which("tool", path=""))
+        self.assertRaises(TypeError, fpath.which, b"tool", path=self.search_path)
+
+    def test_which_all(self):
+        self.assertEqual(fpath.which_all("tool", path=self.search_path),
+                         [os.path.join(self.bin1, "tool"), os.path.join(self.bin2, "tool")])
+        self.assertEqual(fpath.which_all("data", os.R_OK, path=self.search_path), [os.path.join(self.bin1, "data")])
+        self.assertEqual(fpath.which_all("missing", path=self.search_path), [])
+        self.assertRaises(TypeError, fpath.which_all, b"tool", path=self.search_path)
+        self.assertRaises(TypeError, fpath.which_all, "tool", path=os.fsencode(self.search_path))
+
+    def test_expanduser(self):
+        home = os.environ.get("HOME")
+        os.environ["HOME"] = self.root
+        try:
+            self.assertEqual(fpath.which("tool", path="~/bin2"), os.path.join(self.bin2, "tool"))
+            # repeats are skipped once `~` is expanded
+            self.assertEqual(fpath.which_all("tool", path=os.pathsep.join(["~/bin2", self.bin2])),
+                             [os.path.join(self.bin2, "tool")])
+        finally:
+            if home is None:
+                del os.environ["HOME"]
+            else:
+                os.environ["HOME"] = home
+
+    def test_default_path(self):
+        path = os.environ.pop("PATH", None)
+        try:
+            self.assertEqual(fpath.which("sh"), shutil.which("sh"))
+            self.assertEqual(fpath.which_all("sh")[:1], [shutil.which("sh")])
+        finally:
+            if path is not None:
+                os.environ["PATH"] = path
+
+    def test_cache_invalidation(self):
+        old = time.time() - 60
+        os.utime(self.bin1, (old, old))
+        self.assertEqual(fpath.which("tool", path=self.bin1), os.path.join(self.bin1, "tool"))
+        make_tree(self.root, ["bin1/new"])
+        os.chmod(os.path.join(self.bin1, "new"), 0o755)
+        os.utime(self.bin1, (old + 1, old + 1))
+        self.assertEqual(fpath.which("new", path=self.bin1), os.path.join(self.bin1, "new"))
+        os.chmod(os.path.join(self.bin1, "tool"), 0o644)
+        self.assertIsNone(fpath.which("tool", path=self.bin1))
+        fpath.which_cache_clear()
+
+
+class PathlistTest(unittest.TestCase):
+
+    def test_split_join(self):
+        self.assertEqual(fpath.pathlist.split("/usr/bin::/bin:"), ["/usr/bin", "", "/bin", ""])
+        self.assertEqual(fpath.pathlist.split(b"/a:/b"), [b"/a", b"/b"])
+        self.assertEqual(fpath.pathlist.split(""), [])
+        self.assertEqual(fpath.pathlist.join(["/a", "", "/b"]), "/a::/b")
+        self.assertEqual(fpath.pathlist.join(fpath.pathlist.split("/a::/b")), "/a::/b")
+        self.assertEqual(fpath.pathlist.join([b"/a"]), b"/a")
+        self.assertEqual(fpath.pathlist.join([]), "")
+        self.assertRaises(ValueError, fpath.pathlist.join, ["/a:/b"])
+        self.assertRaises(TypeError, fpath.pathlist.join, ["/a", b"/b"])
+
+    def test_modify(self):
+        path = "/usr/bin:/bin/:/usr/bin:"
+        self.assertEqual(fpath.pathlist.normalize(path), "/usr/bin:/bin:.")
+        self.assertEqual(fpath.pathlist.prepend(path, "/opt/bin", "/bin"), "/opt/bin:/bin:/usr/bin:.")
+        self.assertEqual(fpath.pathlist.append(path, "/opt/bin/", "/bin"), "/usr/bin:/bin:.:/opt/bin")
+        self.assertEqual(fpath.pathlist.remove(path, "/usr/bin/", ""), "/bin")
+        self.assertEqual(fpath.pathlist.prepend(b"", b"/x"), b"/x")
+        self.assertRaises(TypeError, fpath.pathlist.append, "/a", b"/b")
+
+    def test_expand(self):
+        env = dict(os.environ)
+        os.environ.update(HOME="/home/me", FPATH_TEST_LIST="/x:/y")
+        try:
+            self.assertEqual(fpath.pathlist.expand("~/bin:$FPATH_TEST_LIST::$FPATH_TEST_UNSET"),
+                             "/home/me/bin:/x:/y::$FPATH_TEST_UNSET")
+        finally:
+            os.environ.clear()
+            os.environ.update(env)
+
+    def test_find(self):
+        with tempfile.TemporaryDirectory() as root:
+            make_tree(root, ["a/", "b/conf", "c/conf"])
+            path = os.pathsep.join(os.path.join(root, d) for d in "abc")
+            self.assertEqual(fpath.pathlist.find(path, "conf"), os.path.join(root, "b", "conf"))
+            self.assertIsNone(fpath.pathlist.find(path, "missing"))
+            self.assertEqual(fpath.pathlist.find(os.fsencode(path), b"conf"), os.fsencode(os.path.join(root, "b", "conf")))
+
+
+class RealpathCacheTest(TreeTestCase):
+
+    tree = ["a/file", "b/file"]
+
+    def setUp(self):
+        super().setUp()
+        os.symlink("a", self.path("link"))
+        os.symlink("link/file", self.path("chain"))
+        self.link = self.path("link")
+
+    def retarget(self, target):
+        os.remove(self.link)
+        os.symlink(target, self.link)
+
+    def test_matches_realpath(self):
+        cache = fpath.RealpathCache()
+        for p in ["chain", "link/../b", "link/missing/..", "a/file", "nonexistent/x"]:
+            path = self.path(p)
+            self.assertEqual(cache.realpath(path), os.path.realpath(path))
+            self.assertEqual(fpath.realpath(path, cache=cache), os.path.realpath(path))
+        self.assertEqual(cache.realpath(os.fsencode(self.link)), os.fsencode(self.path("a")))
+        self.assertGreater(len(cache), 0)
+        self.assertRaises(FileNotFoundError, cache.realpath, self.path("nonexistent"), strict=True)
+        self.assertRaises(TypeError, fpath.realpath, self.link, cache="cache")
+        self.assertRaises(ValueError, fpath.RealpathCache, ttl=-1)
+
+    def test_relative(self):
+        cache = fpath.RealpathCache()
+        cwd = os.getcwd()
+        os.chdir(self.root)
+        try:
+            self.assertEqual(cache.realpath("chain"), self.path("a", "file"))
+            self.assertEqual(cache.realpath("link/.."), self.root)
+        finally:
+            os.chdir(cwd)
+
+    def test_clear(self):
+        cache = fpath.RealpathCache()
+        chain = self.path("chain")
+        self.assertEqual(cache.realpath(chain), self.path("a", "file"))
+        self.retarget("b")
+        self.assertEqual(cache.realpath(chain), self.path("a", "file"))
+        # the resolved prefix is reused for paths never asked for before
+        self.assertEqual(cache.realpath(self.path("link", "new")), self.path("a", "new"))
+        cache.clear()
+        self.assertEqual(len(cache), 0)
+        self.assertEqual(cache.realpath(chain), self.path("b", "file"))
+
+    def test_ttl(self):
+        chain = self.path("chain")
+        cache = fpath.RealpathCache(ttl=0)
+        self.assertEqual(cache.realpath(chain), self.path("a", "file"))
+        self.retarget("b")
+        self.assertEqual(cache.realpath(chain), self.path("b", "file"))
+        self.assertRaises(TypeError, fpath.RealpathCache, check_mtime=True)
+
+    def test_errors_not_cached(self):
+        cache = fpath.RealpathCache()
+        missing = self.path("link", "missing", "x")
+        self.assertEqual(cache.realpath(missing), self.path("a", "missing", "x"))
+        os.makedirs(self.path("b", "missing"))
+        os.symlink(self.path("b", "missing"), self.path("a", "missing"))
+        self.assertEqual(cache.realpath(missing), self.path("b", "missing", "x"))
+
+    def test_threads(self):
+        import threading
+        cache = fpath.RealpathCache()
+        paths = [self.path(p) for p in ["chain", "link", "link/file", "b/file"]] * 50
+        expected = [os.path.realpath(p) for p in paths]
+        results = []
+
+        def run():
+            results.append([cache.realpath(p) for p in paths])
+        threads = [threading.Thread(target=run) for _ in range(8)]
+        for t in threads:
+            t.start()
+        for t in threads:
+            t.join()
+        self.assertEqual(results, [expected] * 8)
+
+
+class RealpathRootTest(TreeTestCase):
+
+    tree = ["lib/libc.so", "etc/passwd"]
+
+    def setUp(self):
+        super().setUp()
+        os.makedirs(self.path("usr"))
+        os.symlink("/lib", self.path("usr", "lib"))
+        os.symlink("../../../../../etc", self.path("usr", "etc"))
+        os.symlink("/usr/lib/libc.so", self.path("libc"))
+        os.symlink("loop", self.path("loop"))
+
+    def test_absolute_links(self):
+        self.assertEqual(fpath.realpath("/usr/lib/libc.so", root=self.root), self.path("lib", "libc.so"))
+        self.assertEqual(fpath.realpath("libc", root=self.root), self.path("lib", "libc.so"))
+        self.assertEqual(fpath.realpath("/libc", root=self.root + "/"), self.path("lib", "libc.so"))
+        self.assertEqual(fpath.realpath(b"/usr/lib", root=os.fsencode(self.root)), os.fsencode(self.path("lib")))
+
+    def test_dotdot_clamped(self):
+        self.assertEqual(fpath.realpath("/usr/etc/passwd", root=self.root), self.path("etc", "passwd"))
+        self.assertEqual(fpath.realpath("../../..", root=self.root), self.root)
+        self.assertEqual(fpath.realpath("/usr/../../lib", root=self.root), self.path("lib"))
+        self.assertEqual(fpath.realpath("/loop/../../x", root=self.root), self.path("x"))
+
+    def test_strict(self):
+        self.assertEqual(fpath.realpath("/usr/lib", root=self.root, strict=True), self.path("lib"))
+        self.assertRaises(FileNotFoundError, fpath.realpath, "/usr/missing", root=self.root, strict=True)
+
+    def test_cache_and_errors(self):
+        cache = fpath.RealpathCache()
+        self.assertEqual(cache.realpath("/usr/lib", root=self.root), self.path("lib"))
+        self.assertEqual(fpath.realpath("/usr/lib", root=self.root, cache=cache), self.path("lib"))
+        self.assertEqual(fpath.realpath("/usr", root="/"), os.path.realpath("/usr"))
+        self.assertRaises(TypeError, fpath.realpath, "/usr", root=os.fsencode(self.root))
+        self.assertRaises(TypeError, cache.realpath, b"/usr", root=self.root)
+
+
+class SecureJoinTest(TreeTestCase):
+
+    tree = ["root/uploads/", "root/data/f", "outside/secret"]
+
+    def setUp(self):
+        super().setUp()
+        self.base = self.root
+        self.root = self.path("root")
+        os.symlink("../../outside", self.path("uploads", "escape"))
+        os.symlink(os.path.join(self.base, "outside"), self.path("abs"))
+        os.symlink("/data", self.path("data_link"))
+        os.symlink("loop", self.path("loop"))
+
+    def test_secure_join(self):
+        join = fpath.secure_join
+        self.assertEqual(join(self.root, "uploads/a.txt"), self.path("uploads", "a.txt"))
+        self.assertEqual(join(self.root, "../../outside/secret"), self.path("outside", "secret"))
+        self.assertEqual(join(self.root, "/etc/passwd"), self.path("etc", "passwd"))
+        self.assertEqual(join(self.root, "uploads/escape/secret"), self.path("outside", "secret"))
+        self.assertEqual(join(self.root, "abs"), self.root + os.path.join(self.base, "outside"))
+        self.assertEqual(join(self.root, "data_link/f"), self.path("data", "f"))
+        self.assertEqual(join(self.root, ""), self.root)
+        self.assertEqual(join(os.fsencode(self.root), b"uploads/.."), os.fsencode(self.root))
+        with self.assertRaises(OSError) as cm:
+            join(self.root, "loop/x")
+        self.assertEqual(cm.exception.errno, errno.ELOOP)
+        self.assertRaises(TypeError, join, self.root, b"x")
+
+    def test_is_within(self):
+        escape = self.path("uploads", "escape", "secret")
+        self.assertTrue(fpath.is_within(self.root, self.path("data", "f")))
+        self.assertTrue(fpath.is_within(self.root, self.root))
+        self.assertFalse(fpath.is_within(self.root, escape))
+        self.assertTrue(fpath.is_within(self.root, escape, resolve=False))
+        self.assertFalse(fpath.is_within(self.root, self.path("..", "outside"), resolve=False))
+        self.assertFalse(fpath.is_within(self.root, self.root + "2"))
+        self.assertTrue(fpath.is_within(os.fsencode(self.root), os.fsencode(self.root + "/data")))
+        self.assertRaises(TypeError, fpath.is_within, self.root, b"x")
+
+
+@unittest.skipUnless(hasattr(fpath, "resolve_beneath"), "Linux only")
+class ResolveBeneathTest(TreeTestCase):
+
+    tree = ["root/a/file", "root/b/file", "outside/file"]
+
+    def setUp(self):
+        super().setUp()
+        self.base = self.root
+        self.root = self.path("root")
+        os.symlink("a", self.path("link"))
+        os.symlink("../link/file", self.path("b", "up"))
+        os.symlink("../outside", self.path("escape"))
+        os.symlink(self.path("a"), self.path("absolute"))
+        os.symlink("loop", self.path("loop"))
+        os.symlink("/proc/self/root", self.path("magic"))
+
+    def resolve(self, path, **kwargs):
+        for use_openat2 in [True, False]:
+            yield fpath.resolve_beneath(self.root, path, use_openat2=use_openat2, **kwargs)
+
+    def assertErrno(self, path, code):
+        for use_openat2 in [True, False]:
+            with self.assertRaises(OSError) as cm:
+                fpath.resolve_beneath(self.root, path, use_openat2=use_openat2)
+            self.assertEqual(cm.exception.errno, code, (path, use_openat2))
+
+    def test_resolve(self):
+        a_file = self.path("a", "file")
+        for p in self.resolve("b/up"):
+            self.assertEqual(p, a_file)
+        for p in self.resolve("link/../b/./"):
+            self.assertEqual(p, self.path("b"))
+        for p in self.resolve(""):
+            self.assertEqual(p, self.root)
+        for p in self.resolve("link", flags=os.O_PATH | os.O_NOFOLLOW):
+            self.assertEqual(p, self.path("link"))
+        self.assertEqual(fpath.resolve_beneath(os.fsencode(self.root), b"link"), os.fsencode(self.path("a")))
+
+    def test_adversarial(self):
+        for p in ["escape/file", "../outside", "a/../../outside", "absolute/file", "/etc", "magic"]:
+            self.assertErrno(p, errno.EXDEV)
+        self.assertErrno("loop", errno.ELOOP)
+        self.assertErrno("missing", errno.ENOENT)
+        self.assertErrno("a/file/x", errno.ENOTDIR)
+
+    def test_return_fd(self):
+        for path, fd in self.resolve("b/up", return_fd=True, flags=os.O_RDONLY):
+            try:
+                self.assertEqual(os.read(fd, 100), b"root/a/file")
+                self.assertEqual(os.path.samestat(os.fstat(fd), os.stat(path)), True)
+            finally:
+                os.close(fd)
+        for path, fd in self.resolve("link", return_fd=True):
+            os.close(fd)
+        self.assertRaises(ValueError, fpath.resolve_beneath, self.root, "x", flags=os.O_CREAT | os.O_WRONLY)
+        self.assertRaises(TypeError, fpath.resolve_beneath, self.root, b"x")
+
+    def test_swapped_symlink(self):
+        import threading
+        swap = self.path("swap")
+        tmp = swap + ".tmp"
+        os.symlink("a", swap)
+        done = threading.Event()
+
+        def flip():
+            targets = ["a", "../outside"]
+            i = 0
+            while not done.is_set():
+                os.symlink(targets[i % 2], tmp)
+                os.replace(tmp, swap)
+                i += 1
+        t = threading.Thread(target=flip)
+        t.start()
+        try:
+            for i in range(2000):
+                try:
+                    p = fpath.resolve_beneath(self.root, "swap/file", use_openat2=bool(i % 2))
+                except OSError as e:
+                    self.assertIn(e.errno, (errno.EXDEV, errno.ENOENT))
+                    continue
+                self.assertEqual(p, self.path("a", "file"))
+        finally:
+            done.set()
+            t.join()
+
+
+class ResolveTraceTest(TreeTestCase):
+
+    tree = ["a/file"]
+
+    def setUp(self):
+        super().setUp()
+        os.symlink("a", self.path("link"))
+        os.symlink("link/file", self.path("chain"))
+        os.symlink("loop", self.path("loop"))
+
+    def steps(self, path):
+        return [(s["action"], s["path"][len(self.root) + 1:], s["target"])
+                for s in fpath.resolve_trace(self.path(path))
+                if s["path"].startswith(self.root + os.sep)]
+
+    def test_chain(self):
+        self.assertEqual(self.steps("chain"), [
+            ("symlink", "chain", "link/file"),
+            ("symlink", "link", "a"),
+            ("component", "a", None),
+            ("resolved", "link", None),
+            ("component", os.path.join("a", "file"), None),
+            ("resolved", "chain", None),
+            ("result", os.path.join("a", "file"), None),
+        ])
+
+    def test_annotations(self):
+        self.assertEqual(self.steps("link/../missing"), [
+            ("symlink", "link", "a"),
+            ("component", "a", None),
+            ("resolved", "link", None),
+            ("parent", os.path.join("a", ".."), None),
+            ("missing", "missing", None),
+            ("result", "missing", None),
+        ])
+        self.assertEqual([s[0] for s in self.steps("loop/x")], ["symlink", "loop", "result"])
+
+    def test_matches_realpath(self):
+        cwd = os.getcwd()
+        os.chdir(self.root)
+        try:
+            for p in ["chain", "link/..", "loop/x", "../a", "missing/../link", b"chain"]:
+                trace = fpath.resolve_trace(p)
+                self.assertEqual(trace[-1]["action"], "result")
+                self.assertEqual(trace[-1]["resolved"], os.path.realpath(p))
+                self.assertEqual(trace[-1]["resolved"], fpath.realpath(p))
+        finally:
+            os.chdir(cwd)
+
+
+class RealpathErrorTest(TreeTestCase):
+
+    tree = ["file"]
+
+    def setUp(self):
+        super().setUp()
+        os.symlink("loop", self.path("loop"))
+        os.symlink("b", self.path("a"))
+        os.symlink("a", self.path("b"))
+
+    def test_strict_loop(self):
+        for name in ["loop", "a"]:
+            with self.assertRaises(OSError) as cm:
+                fpath.realpath(self.path(name, "x"), strict=True)
+            self.assertEqual(cm.exception.errno, errno.ELOOP)
+            self.assertEqual(cm.exception.filename, self.path(name))
+            self.assertEqual(fpath.realpath(self.path(name, "x")), os.path.realpath(self.path(name, "x")))
+
+    def test_strict_subclasses(self):
+        with self.assertRaises(FileNotFoundError) as cm:
+            fpath.realpath(self.path("missing", "x"), strict=True)
+        self.assertEqual(cm.exception.filename, self.path("missing"))
+        self.assertRaises(NotADirectoryError, fpath.realpath, self.path("file", "x"), strict=True)
+        self.assertEqual(fpath.realpath(self.path("file", "x")), self.path("file", "x"))
+
+    @unittest.skipIf(os.geteuid() == 0, "root ignores permissions")
+    def test_strict_permission(self):
+        make_tree(self.root, ["locked/x"])
+        os.chmod(self.path("locked"), 0)
+        try:
+            self.assertRaises(PermissionError, fpath.realpath, self.path("locked", "x"), strict=True)
+            self.assertEqual(fpath.realpath(self.path("locked", "x")), self.path("locked", "x"))
+        finally:
+            os.chmod(self.path("locked"), 0o755)
+
+    def test_symlink_limit(self):
+        make_tree(self.root, ["target/file"])
+        for i in range(41):
+            os.symlink("link%d" % (i + 1) if i < 40 else "target", self.path("link%d" % i))
+        self.assertEqual(fpath.realpath(self.path("link1", "file"), strict=True), self.path("target", "file"))
+        with self.assertRaises(OSError) as cm:
+            fpath.realpath(self.path("link0", "file"), strict=True)
+        self.assertEqual(cm.exception.errno, errno.ELOOP)
+        self.assertEqual(fpath.realpath(self.path("link0", "file")), self.path("link40", "file"))
+
+    def test_deep_chain(self):
+        parent = self.root
+        for i in range(41):
+            os.mkdir(os.path.join(parent, "real"))
+            os.symlink("real", os.path.join(parent, "dir"))
+            parent = os.path.join(parent, "real")
+        deep = self.path(*["dir"] * 40)
+        self.assertEqual(fpath.realpath(deep, strict=True), os.path.realpath(deep, strict=True))
+        # like the kernel, the limit counts every link on the way
+        deep = os.path.join(deep, "dir")
+        self.assertRaises(OSError, fpath.realpath, deep, strict=True)
+        self.assertEqual(fpath.realpath(deep), os.path.join(os.path.dirname(os.path.realpath(deep)), "dir"))
+
+    def test_non_utf8_target(self):
+        os.symlink(b"\xff", os.fsencode(self.path("bad")))
+        self.assertRaises(UnicodeDecodeError, fpath.realpath, self.path("bad"))
+
+    def test_arguments(self):
+        self.assertRaises(TypeError, fpath.realpath, self.root, strict="yes")
+        self.assertRaises(TypeError, fpath.realpath, self.root, bogus=True)
+        self.assertRaises(TypeError, fpath.realpath, self.root, True)
+        self.assertRaises(ValueError, fpath.realpath, self.root + "\0x")
+        self.assertRaises(ValueError, fpath.realpath, self.root + "\0x", strict=True)
+
+    def test_replaced_during_resolution(self):
+        import threading
+        flip = self.path("flip")
+        tmp = flip + ".tmp"
+        done = threading.Event()
+
+        def run():
+            while not done.is_set():
+                os.symlink("file", tmp)
+                os.replace(tmp, flip)
+                os.remove(flip)
+                with open(tmp, "w"):
+                    pass
+                os.replace(tmp, flip)
+        t = threading.Thread(target=run)
+        t.start()
+        try:
+            for _ in range(2000):
+                self.assertIn(fpath.realpath(flip), (flip, self.path("file")))
+        finally:
+            done.set()
+            t.join()
+
+
+class DirFdTest(TreeTestCase):
+
+    tree = ["sub/file", "sub/dir/"]
+
+    def setUp(self):
+        super().setUp()
+        os.symlink("file", self.path("sub", "link"))
+        os.symlink("missing", self.path("sub", "broken"))
+        os.symlink("dir", self.path("sub", "dirlink"))
+        self.fd = os.open(self.path("sub"), os.O_RDONLY | os.O_DIRECTORY)
+        self.addCleanup(os.close, self.fd)
+
+    def test_predicates(self):
+        fd = self.fd
+        for name in ["file", "dir", "link", "dirlink", b"file"]:
+            self.assertTrue(fpath.exists(name, dir_fd=fd), name)
+            self.assertTrue(fpath.lexists(name, dir_fd=fd), name)
+        self.assertFalse(fpath.exists("broken", dir_fd=fd))
+        self.assertTrue(fpath.exists("broken", dir_fd=fd, follow_symlinks=False))
+        self.assertTrue(fpath.lexists("broken", dir_fd=fd))
+        self.assertFalse(fpath.lexists("missing", dir_fd=fd))
+        self.assertTrue(fpath.islink("link", dir_fd=fd))
+        self.assertTrue(fpath.islink(b"broken", dir_fd=fd))
+        self.assertFalse(fpath.islink("file", dir_fd=fd))
+        self.assertTrue(fpath.isfile("link", dir_fd=fd))
+        self.assertFalse(fpath.isfile("link", dir_fd=fd, follow_symlinks=False))
+        self.assertTrue(fpath.isdir("dirlink", dir_fd=fd))
+        self.assertFalse(fpath.isdir("dirlink", dir_fd=fd, follow_symlinks=False))
+        self.assertFalse(fpath.isdir("file", dir_fd=fd))
+        # absolute paths ignore dir_fd, as with the *at() calls
+        self.assertTrue(fpath.isdir(self.root, dir_fd=fd))
+        self.assertFalse(fpath.exists("file\0", dir_fd=fd))
+        self.assertFalse(fpath.exists("file", dir_fd=9999))
+
+    def test_follow_symlinks_without_dir_fd(self):
+        link = self.path("sub", "broken")
+        self.assertFalse(fpath.exists(link))
+        self.assertTrue(fpath.exists(link, follow_symlinks=False))
+        self.assertFalse(fpath.isfile(self.path("sub", "link"), follow_symlinks=False))
+
+    def test_realpath(self):
+        sub = self.path("sub")
+        self.assertEqual(fpath.realpath("link", dir_fd=self.fd), os.path.join(sub, "file"))
+        self.assertEqual(fpath.realpath("dirlink/..", dir_fd=self.fd), sub)
+        self.assertEqual(fpath.realpath(b"link", dir_fd=self.fd), os.fsencode(os.path.join(sub, "file")))
+        self.assertEqual(fpath.realpath(self.root, dir_fd=self.fd), self.root)
+        self.assertRaises(FileNotFoundError, fpath.realpath, "broken", dir_fd=self.fd, strict=True)
+        self.assertEqual(fpath.realpath("../sub/link", dir_fd=self.fd), os.path.join(sub, "file"))
+        os.symlink(self.root, os.path.join(sub, "up"))
+        self.assertEqual(fpath.realpath("up/sub/dirlink", dir_fd=self.fd, strict=True), os.path.join(sub, "dir"))
+        self.assertRaises(ValueError, fpath.realpath, "link", dir_fd=self.fd, root="/")
+        self.assertRaises(ValueError, fpath.realpath, "link", dir_fd=self.fd, cache=fpath.RealpathCache())
+        with open(os.path.join(sub, "file")) as f:
+            self.assertRaises(NotADirectoryError, fpath.realpath, "x", dir_fd=f.fileno())
+
+    def test_realpath_moved(self):
+        # components are looked up through the fd, whatever its path now is
+        moved = self.path("moved")
+        os.rename(self.path("sub"), moved)
+        self.assertEqual(fpath.realpath("link", dir_fd=self.fd, strict=True), os.path.join(moved, "file"))
+        self.assertEqual(fpath.realpath("dirlink", dir_fd=self.fd), os.path.join(moved, "dir"))
+
+
+class PhysicalRelpathTest(TreeTestCase):
+
+    tree = ["releases/v1/app/bin", "shared/config"]
+
+    def setUp(self):
+        super().setUp()
+        os.symlink("releases/v1", self.path("current"))
+
+    def test_relpath(self):
+        config, app = self.path("shared", "config"), self.path("current", "app")
+        self.assertEqual(fpath.relpath(config, app), os.path.join("..", "..", "shared", "config"))
+        self.assertEqual(fpath.relpath(config, app, resolve=True), os.path.join("..", "..", "..", "shared", "config"))
+        self.assertEqual(fpath.relpath(os.fsencode(config), os.fsencode(app), resolve=True),
+                         os.fsencode(os.path.join("..", "..", "..", "shared", "config")))
+        self.assertEqual(fpath.relpath(self.path("releases", "v1"), self.path("current"), resolve=True), ".")
+        cwd = os.getcwd()
+        os.chdir(self.path("current"))
+        try:
+            self.assertEqual(fpath.relpath(config, resolve=True), os.path.join("..", "..", "shared", "config"))
+        finally:
+            os.chdir(cwd)
+
+    def test_symlink_target(self):
+        config = self.path("shared", "config")
+        link = self.path("current", "app", "config")
+        text = fpath.symlink_target(config, link)
+        self.assertEqual(text, os.path.join("..", "..", "..", "shared", "config"))
+        os.symlink(text, link)
+        self.assertEqual(os.path.realpath(link), config)
+        # a link to a link keeps pointing at the link
+        link2 = self.path("shared", "app_link")
+        os.symlink(fpath.symlink_target(self.path("current"), link2), link2)
+        self.assertEqual(os.readlink(link2), "../current")
+        self.assertEqual(fpath.symlink_target(self.path("current", "app", ".."), link2), "../releases/v1")
+        self.assertEqual(fpath.symlink_target(os.fsencode(config), os.fsencode(link)), os.fsencode(text))
+        self.assertRaises(TypeError, fpath.symlink_target, config, os.fsencode(link))
+        self.assertRaises(ValueError, fpath.symlink_target, "", link)
+
+
+class ResolveVirtualTest(TreeTestCase):
+
+    dirs = ["a/b/c", "d"]
+    links = {"a/up": "..", "a/b/self": ".", "x": "a/b", "y": "x/c/../..", "loop1": "loop2",
+             "loop2": "loop1", "d/dangling": "nowhere/far", "a/b/c/back": "../../../y"}
+    tree = [d + "/" for d in dirs]
+
+    def setUp(self):
+        super().setUp()
+        for link, target in self.links.items():
+            os.symlink(target, self.path(link))
+        self.vlinks = {self.path(k): v for k, v in self.links.items()}
+        self.existing = [self.path(d) for d in self.dirs]
+
+    def test_matches_realpath(self):
+        names = ["", ".", "..", "a", "up", "b", "self", "c", "back", "x", "y", "loop1", "d", "dangling", "nope"]
+        paths = [os.path.join(p, q) for p in names for q in names] + ["x/c/back/up/b/self//c", "loop1/a", "d/dangling/.."]
+        for p in paths:
+            full = self.path(p)
+            self.assertEqual(fpath.resolve_virtual(full, self.vlinks), os.path.realpath(full), p)
+            self.assertEqual(fpath.resolve_virtual(p, self.vlinks, cwd=self.root), os.path.realpath(full), p)
+            try:
+                expected = os.path.realpath(full, strict=True)
+            except OSError as e:
+                with self.assertRaises(OSError, msg=p) as cm:
+                    fpath.resolve_virtual(full, self.vlinks, strict=True, existing=self.existing)
+                self.assertEqual(cm.exception.errno, e.errno, p)
+            else:
+                self.assertEqual(fpath.resolve_virtual(full, self.vlinks, True, existing=self.existing), expected, p)
+
+    def test_no_disk_access(self):
+        links = {"/etc": "/nonexistent/etc", "/nonexistent/etc/l": "../../usr"}
+        self.assertEqual(fpath.resolve_virtual("/etc/l/bin", links), "/usr/bin")
+        # without `existing` every path that isn't a link exists
+        self.assertEqual(fpath.resolve_virtual("/no/such/file", {}, strict=True), "/no/such/file")
+        with self.assertRaises(FileNotFoundError):
+            fpath.resolve_virtual("/no/such/file", {}, strict=True, existing=["/no/such"])
+        self.assertEqual(fpath.resolve_virtual("/no/such", {}, strict=True, existing=["/no/such/file"]), "/no/such")
+        with self.assertRaises(OSError) as cm:
+            fpath.resolve_virtual("/l", {"/l": "l"}, strict=True)
+        self.assertEqual(cm.exception.errno, errno.ELOOP)
+        self.assertEqual(cm.exception.filename, "/l")
+
+    def test_arguments(self):
+        self.assertEqual(fpath.resolve_virtual(b"/a/l", {b"/a/l": b"../b"}), b"/b")
+        self.assertEqual(fpath.resolve_virtual("l", {"l": "b"}, cwd="/a/"), "/a/b")
+        with self.assertRaises(TypeError):
+            fpath.resolve_virtual("/a/l", {b"/a/l": "b"})
+        with self.assertRaises(TypeError):
+            fpath.resolve_virtual("/a", {}, existing=[b"/a"])
+        with self.assertRaises(ValueError):
+            fpath.resolve_virtual("l", {}, cwd="relative")
+        with self.assertRaises(ValueError):
+            fpath.resolve_virtual("/a\0", {})
+
+if __name__ == '__main__':
+    unittest.main()